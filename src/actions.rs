@@ -1,121 +1,1260 @@
-use std::error::Error;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::panic;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
+use async_stream::try_stream;
+use chrono::prelude::*;
 use colored::Colorize;
+use futures::future::{self, Either};
 use futures::pin_mut;
-use futures::stream::StreamExt;
+use futures::stream::{Stream, StreamExt};
 use log::{error, info, trace, warn};
+use sha2::{Digest, Sha256};
 
+use crate::alias::{resolve_aliases, AliasRoot, ResolvedAlias};
 use crate::archive::{EmojiDirectory, EmojiFile};
-use crate::emoji::{new_emoji_stream, EmojiCollection, EmojiExistenceKind, EmojiStreamParameters};
-use crate::slack::SlackClient;
+use crate::compact::compact_directory;
+use crate::diff::EmojiDiff;
+use crate::emoji::{
+    new_emoji_stream, Emoji, EmojiCollection, EmojiExistenceKind, EmojiStreamParameters,
+};
+use crate::error::{Error, Result};
+use crate::filter::EmojiFilter;
+use crate::journal::UploadJournal;
+use crate::report::{EmojiOutcome, RunReport};
+use crate::slack::{CredentialCheck, SlackClient};
+use crate::verify::verify_directory;
+
+pub const DEFAULT_DOWNLOAD_CONCURRENCY: usize = 8;
+pub const DEFAULT_REFETCH_AFTER_MINS: u64 = 60;
 
 // See build.rs
 include!(concat!(env!("OUT_DIR"), "/emoji_standard_shortcodes.rs"));
 
-pub async fn download(
-    client: Rc<SlackClient>,
-    target_directory: &str,
+/// What a subcommand needs to be able to do with the emojis of a workspace. Only `ListAndAdd` is probed by sending a
+/// request to emoji.add, so subcommands that change nothing, including dry runs, should require `List`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequiredAccess {
+    List,
+    ListAndAdd,
+}
+
+/// Checks that the credentials of `client` work and allow `required_access`, so that a subcommand fails before doing
+/// anything rather than partway through
+pub async fn preflight(
+    client: &SlackClient,
+    required_access: RequiredAccess,
+) -> Result<CredentialCheck> {
+    let check = client
+        .check_credentials(required_access == RequiredAccess::ListAndAdd)
+        .await?;
+    let identity = &check.identity;
+    info!(
+        "Authenticated as {} in {} ({})",
+        identity.user, identity.team, identity.url
+    );
+
+    let missing_action = if !check.can_list_emojis {
+        Some("list emojis")
+    } else if check.can_add_emojis == Some(false) {
+        Some("add emojis")
+    } else {
+        None
+    };
+    match missing_action {
+        Some(action) => Err(Error::PermissionDenied {
+            user: identity.user.clone(),
+            team: identity.team.clone(),
+            action: action.to_string(),
+        }),
+        None => Ok(check),
+    }
+}
+
+/// Prints who the credentials of `client` belong to and whether they can list and add emojis
+pub async fn whoami(client: Arc<SlackClient>) -> Result<()> {
+    let check = client.check_credentials(true).await?;
+    let identity = &check.identity;
+    let describe = |allowed: bool| if allowed { "yes".green() } else { "no".red() };
+
+    println!("User: {} ({})", identity.user, identity.user_id);
+    println!("Team: {} ({})", identity.team, identity.team_id);
+    println!("URL: {}", identity.url);
+    println!("Can list emojis: {}", describe(check.can_list_emojis));
+    println!(
+        "Can add emojis: {}",
+        describe(check.can_add_emojis == Some(true))
+    );
+
+    Ok(())
+}
+
+/// An entry of the emoji list on its way through `download`
+enum DownloadStep {
+    ListFailed(Error),
+    AlreadyDownloaded(EmojiFile),
+    /// Already downloaded but marked as deleted upstream
+    Reappeared(EmojiFile),
+    /// Would have been downloaded if not for a dry run
+    Planned(EmojiFile),
+    Attempted(EmojiFile, Result<()>),
+}
+
+/// Downloads emojis that are not yet in `target_directory`, with up to `options.concurrency` images downloading at
+/// once.
+/// Emojis are recorded in the metadata file in the order that Slack lists them, regardless of which image finishes
+/// downloading first. Emojis that fail to download are reported rather than stopping the run.
+///
+/// Emojis that have changed since they were downloaded are downloaded again as a new revision, which replaces the
+/// previous one in the metadata file once the run is over; the previous revision is kept in the history file.
+///
+/// Only emojis selected by `filter` are downloaded. If `options.mark_deleted` is set, emojis in the archive that
+/// `filter` selects but are missing from the workspace are marked as deleted upstream. That is only done when every
+/// emoji in the workspace was listed, since an emoji missing from a partial list may well still exist. Emojis marked
+/// as deleted upstream that are listed again are no longer marked, whether or not `options.mark_deleted` is set.
+///
+/// If `options.dry_run` is set, the report says what would be downloaded and marked, but nothing is written to disk.
+pub async fn download<P: AsRef<Path>>(
+    client: Arc<SlackClient>,
+    target_directory: P,
     stream_parameters: EmojiStreamParameters,
-) -> Result<(), Box<dyn Error>> {
-    let stream = new_emoji_stream(client.clone(), Some(stream_parameters));
+    filter: &EmojiFilter,
+    options: &DownloadOptions,
+) -> Result<RunReport> {
+    let dry_run = options.dry_run;
+    let emoji_directory = EmojiDirectory::new(target_directory.as_ref());
+    let mut metadata_file = if dry_run {
+        None
+    } else {
+        emoji_directory.ensure_exists().await?;
+        Some(emoji_directory.open_metadata_file().await?)
+    };
+    let downloaded_emoji_files: HashMap<String, EmojiFile> = emoji_directory
+        .read_emoji_files()
+        .await?
+        .into_iter()
+        .map(|emoji_file| (emoji_file.emoji.name.clone(), emoji_file))
+        .collect();
+
+    let lists_every_page = stream_parameters.lists_every_page();
+    let stream = new_emoji_stream(client.clone(), Some(stream_parameters))
+        .filter(|emoji_result| {
+            future::ready(match emoji_result {
+                Ok(emoji) => filter.matches(emoji),
+                Err(_) => true,
+            })
+        })
+        .map(|emoji_result| {
+            let emoji = match emoji_result {
+                Ok(emoji) => emoji,
+                Err(e) => return Either::Left(future::ready(DownloadStep::ListFailed(e))),
+            };
+            let mut emoji_file = match downloaded_emoji_files.get(&emoji.name) {
+                None => EmojiFile::from(emoji),
+                Some(downloaded) if downloaded.has_changed(&emoji) => {
+                    downloaded.next_revision(emoji)
+                }
+                Some(downloaded) if downloaded.deleted_upstream_at.is_some() => {
+                    return Either::Left(future::ready(DownloadStep::Reappeared(EmojiFile::from(
+                        emoji,
+                    ))))
+                }
+                Some(_) => {
+                    return Either::Left(future::ready(DownloadStep::AlreadyDownloaded(
+                        EmojiFile::from(emoji),
+                    )))
+                }
+            };
+            if dry_run {
+                return Either::Left(future::ready(DownloadStep::Planned(emoji_file)));
+            }
+
+            let client = client.clone();
+            let emoji_directory = emoji_directory.clone();
+            // Spawned so that downloads can run in parallel on a multi-threaded runtime
+            let handle = tokio::spawn(async move {
+                let result = emoji_file
+                    .download_to_directory(client, &emoji_directory)
+                    .await;
+                DownloadStep::Attempted(emoji_file, result)
+            });
+            Either::Right(async move {
+                match handle.await {
+                    Ok(step) => step,
+                    Err(e) => panic::resume_unwind(e.into_panic()),
+                }
+            })
+        })
+        // Unlike buffer_unordered, yields results in the order of the emoji stream
+        .buffered(options.concurrency.max(1));
     pin_mut!(stream);
 
-    let emoji_directory = EmojiDirectory::new(target_directory);
-    emoji_directory.ensure_exists().await;
-    let mut metadata_file = emoji_directory.open_metadata_file().await?;
-    let metadata_emoji_name_set = metadata_file.get_emoji_name_set().await?;
-
-    while let Some(emoji_result) = stream.next().await {
-        match emoji_result {
-            Ok(emoji) => {
-                let emoji_file = EmojiFile::from(emoji);
-                if !metadata_emoji_name_set.contains(&emoji_file.emoji.name) {
-                    emoji_file
-                        .download_to_directory(client.clone(), &emoji_directory)
-                        .await?;
+    let mut report = if dry_run {
+        RunReport::new_dry_run()
+    } else {
+        RunReport::new()
+    };
+    let mut revisions = Vec::new();
+    let mut reappeared_names = HashSet::new();
+    let mut listed_names = HashSet::new();
+    while let Some(step) = stream.next().await {
+        if let DownloadStep::AlreadyDownloaded(emoji_file)
+        | DownloadStep::Reappeared(emoji_file)
+        | DownloadStep::Planned(emoji_file)
+        | DownloadStep::Attempted(emoji_file, _) = &step
+        {
+            listed_names.insert(emoji_file.emoji.name.clone());
+        }
+        match step {
+            DownloadStep::ListFailed(e) => {
+                error!("Failed to fetch emoji list or parse response: {}", e);
+                report.record_error(e);
+            }
+            DownloadStep::AlreadyDownloaded(emoji_file) => {
+                trace!("Emoji is already downloaded; skipping: {:?}", emoji_file);
+                report.record(emoji_file.emoji.name, EmojiOutcome::SkippedExisting);
+            }
+            DownloadStep::Reappeared(emoji_file) => {
+                info!(
+                    "Emoji marked as deleted upstream was listed again: {}",
+                    emoji_file.emoji.name
+                );
+                report.record(emoji_file.emoji.name.clone(), EmojiOutcome::Reappeared);
+                reappeared_names.insert(emoji_file.emoji.name);
+            }
+            DownloadStep::Planned(emoji_file) if emoji_file.revision > 1 => {
+                report.record(emoji_file.emoji.name, EmojiOutcome::Updated);
+            }
+            DownloadStep::Planned(emoji_file) => {
+                report.record(emoji_file.emoji.name, EmojiOutcome::Downloaded);
+            }
+            DownloadStep::Attempted(emoji_file, Ok(())) if emoji_file.revision > 1 => {
+                info!(
+                    "Downloaded revision {} of changed emoji: {:?}",
+                    emoji_file.revision, emoji_file
+                );
+                report.record(emoji_file.emoji.name.clone(), EmojiOutcome::Updated);
+                revisions.push(emoji_file);
+            }
+            DownloadStep::Attempted(emoji_file, Ok(())) => {
+                if let Some(metadata_file) = &mut metadata_file {
                     metadata_file.record_emoji(&emoji_file).await?;
-                    info!("Downloaded emoji: {:?}", emoji_file);
-                } else {
-                    trace!("Emoji is already downloaded; skipping: {:?}", emoji_file);
                 }
+                info!("Downloaded emoji: {:?}", emoji_file);
+                report.record(emoji_file.emoji.name, EmojiOutcome::Downloaded);
+            }
+            DownloadStep::Attempted(emoji_file, Err(e)) => {
+                error!("Failed to download emoji {}: {}", emoji_file.emoji.name, e);
+                report.record(emoji_file.emoji.name, EmojiOutcome::Failed(e.to_string()));
+            }
+        }
+    }
+
+    // Recording revisions and deletions replaces the metadata file, so nothing more may be appended to it
+    drop(metadata_file);
+    if !revisions.is_empty() {
+        emoji_directory.record_revisions(revisions).await?;
+    }
+    if !reappeared_names.is_empty() && !dry_run {
+        emoji_directory
+            .unmark_deleted_upstream(&reappeared_names)
+            .await?;
+    }
+
+    if options.mark_deleted {
+        if lists_every_page && report.errors.is_empty() {
+            let mut deleted_names: Vec<&String> = downloaded_emoji_files
+                .values()
+                .filter(|emoji_file| {
+                    emoji_file.deleted_upstream_at.is_none()
+                        && filter.matches(&emoji_file.emoji)
+                        && !listed_names.contains(&emoji_file.emoji.name)
+                })
+                .map(|emoji_file| &emoji_file.emoji.name)
+                .collect();
+            deleted_names.sort();
+            for name in &deleted_names {
+                info!("Emoji was deleted upstream: {}", name);
+                report.record(name.as_str(), EmojiOutcome::MarkedDeleted);
+            }
+            if !deleted_names.is_empty() && !dry_run {
+                emoji_directory
+                    .mark_deleted_upstream(
+                        &deleted_names.into_iter().cloned().collect(),
+                        Utc::now(),
+                    )
+                    .await?;
+            }
+        } else {
+            warn!("Not marking any emojis as deleted upstream, since not every emoji in the workspace was listed");
+        }
+    }
+
+    Ok(report)
+}
+
+/// What `upload` does with an emoji whose name is taken, either by an emoji in the workspace or by a standard emoji
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ConflictStrategy {
+    /// Leaves the emoji out
+    #[default]
+    Skip,
+    /// Uploads the emoji under its name with `prefix` and `suffix` added instead, and aliases for it as aliases for the
+    /// new name
+    Rename { prefix: String, suffix: String },
+    /// Removes the emoji in the workspace and uploads the one in the archive in its place, unless they are the same.
+    /// Standard emojis cannot be replaced, so emojis named after them are left out. An emoji that cannot be replaced
+    /// is put back along with the aliases for it.
+    Overwrite,
+}
+
+/// How `download` goes about downloading emojis
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// How many images may download at once
+    pub concurrency: usize,
+    /// Marks emojis in the archive that are missing from the workspace as deleted upstream
+    pub mark_deleted: bool,
+    /// Works out what would be downloaded and marked without writing anything to disk
+    pub dry_run: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: DEFAULT_DOWNLOAD_CONCURRENCY,
+            mark_deleted: false,
+            dry_run: false,
+        }
+    }
+}
+
+/// How `upload` goes about uploading emojis
+#[derive(Debug, Clone)]
+pub struct UploadOptions {
+    /// Skips the emojis that an earlier upload already got through, according to its journal
+    pub resume: bool,
+    /// When resuming, the workspace's emojis are only fetched if the journal was last written to longer ago than this
+    pub refetch_after: Duration,
+    /// Also uploads emojis marked as deleted upstream
+    pub include_deleted: bool,
+    pub on_conflict: ConflictStrategy,
+    /// Works out what would be uploaded without uploading anything or writing to the journal
+    pub dry_run: bool,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self {
+            resume: false,
+            refetch_after: Duration::from_secs(DEFAULT_REFETCH_AFTER_MINS * 60),
+            include_deleted: false,
+            on_conflict: ConflictStrategy::Skip,
+            dry_run: false,
+        }
+    }
+}
+
+/// Uploads the emojis in `target_directory` that are missing from the workspace of `client`, recording the outcome
+/// for each in an upload journal. When resuming, emojis found to exist when uploading them are skipped as they would
+/// have been had the workspace's emojis been fetched. Only emojis selected by `filter` are uploaded.
+///
+/// A dry run always fetches the workspace's emojis, so that the report says as accurately as it can what would be
+/// uploaded. So does an upload that resolves conflicts other than by skipping, since it needs to know what they are.
+pub async fn upload<P: AsRef<Path>>(
+    client: Arc<SlackClient>,
+    target_directory: P,
+    filter: &EmojiFilter,
+    options: &UploadOptions,
+) -> Result<RunReport> {
+    if let ConflictStrategy::Rename { prefix, suffix } = &options.on_conflict {
+        if prefix.is_empty() && suffix.is_empty() {
+            return Err(Error::InvalidInput(String::from(
+                "Renaming emojis whose names are taken needs a prefix or a suffix to add",
+            )));
+        }
+    }
+    let emoji_directory = EmojiDirectory::new(target_directory.as_ref());
+    emoji_directory.ensure_is_directory().await?;
+
+    let mut journal = match (options.resume, options.dry_run) {
+        (true, false) => Some(UploadJournal::resume(&emoji_directory).await?),
+        (false, false) => Some(UploadJournal::start(&emoji_directory).await?),
+        (true, true) => UploadJournal::read(&emoji_directory).await?,
+        (false, true) => None,
+    };
+    let completed_names = journal
+        .as_ref()
+        .map(UploadJournal::completed_names)
+        .unwrap_or_default();
+    if !completed_names.is_empty() {
+        info!(
+            "Skipping {} emoji(s) that an earlier upload got through",
+            completed_names.len()
+        );
+    }
+
+    let journal_is_recent = journal
+        .as_ref()
+        .and_then(UploadJournal::last_recorded_at)
+        .is_some_and(|last_recorded_at| {
+            // Counts as recent if the clock has been set back since, i.e. the journal seems to be from the future
+            (Utc::now() - last_recorded_at)
+                .to_std()
+                .map_or(true, |age| age < options.refetch_after)
+        });
+    let existing_emoji_collection = if options.resume
+        && journal_is_recent
+        && !options.dry_run
+        && options.on_conflict == ConflictStrategy::Skip
+    {
+        info!("Upload journal is recent; not fetching the emojis in the workspace again");
+        None
+    } else {
+        Some(EmojiCollection::from_new_emoji_stream(client.clone()).await?)
+    };
+
+    let stream = emoji_directory
+        .stream_emoji_files()
+        .filter(|emoji_file_result| {
+            future::ready(match emoji_file_result {
+                Ok(emoji_file) if !filter.matches(&emoji_file.emoji) => false,
+                Ok(emoji_file)
+                    if emoji_file.deleted_upstream_at.is_some() && !options.include_deleted =>
+                {
+                    trace!("Emoji was deleted upstream; skipping: {:?}", emoji_file);
+                    false
+                }
+                Ok(emoji_file) => !completed_names.contains(&emoji_file.emoji.name),
+                Err(_) => true,
+            })
+        });
+
+    upload_emoji_files(
+        client,
+        &emoji_directory,
+        existing_emoji_collection.as_ref(),
+        stream,
+        if options.dry_run {
+            None
+        } else {
+            journal.as_mut()
+        },
+        &options.on_conflict,
+        options.dry_run,
+    )
+    .await
+}
+
+/// Prints how the emojis in `target_directory` differ from those in the workspace of `client`. Emojis marked as
+/// deleted upstream are listed apart from the rest of those only in the archive, since `upload` leaves them out.
+pub async fn diff<P: AsRef<Path>>(
+    client: Arc<SlackClient>,
+    target_directory: P,
+    as_json: bool,
+) -> Result<()> {
+    let emoji_directory = EmojiDirectory::new(target_directory.as_ref());
+    emoji_directory.ensure_is_directory().await?;
+    let (archived_emoji_files, remote_emoji_collection) = tokio::join!(
+        emoji_directory.read_emoji_files(),
+        EmojiCollection::from_new_emoji_stream(client)
+    );
+    let emoji_diff = EmojiDiff::new(archived_emoji_files?, &remote_emoji_collection?);
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&emoji_diff)?);
+    } else {
+        print!("{}", emoji_diff);
+    }
+
+    Ok(())
+}
+
+/// Checks the archive in `target_directory` for missing or altered images, images that no metadata refers to, and
+/// malformed or duplicate lines of metadata, and prints what it finds
+pub async fn verify<P: AsRef<Path>>(target_directory: P, as_json: bool) -> Result<()> {
+    let emoji_directory = EmojiDirectory::new(target_directory.as_ref());
+    let report = verify_directory(&emoji_directory).await?;
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report);
+    }
+
+    report.into_result().map(|_| ())
+}
+
+/// Rewrites the metadata of the archive in `target_directory` with one record per emoji, sorted by name, leaving out
+/// records whose image is missing, and prints what was left out
+pub async fn compact<P: AsRef<Path>>(target_directory: P) -> Result<()> {
+    let emoji_directory = EmojiDirectory::new(target_directory.as_ref());
+    let report = compact_directory(&emoji_directory).await?;
+    print!("{}", report);
+    Ok(())
+}
+
+/// Deletes the emojis in the workspace of `client` that are selected by `filter`, after listing them and asking for
+/// confirmation (unless `assume_yes` is set). Slack removes the aliases for an emoji along with it, so those are listed
+/// and reported too, even if `filter` does not select them. Emojis that fail to be deleted are reported rather than
+/// stopping the run. If `dry_run` is set, the emojis are only listed and reported as what would be deleted.
+pub async fn delete(
+    client: Arc<SlackClient>,
+    filter: EmojiFilter,
+    assume_yes: bool,
+    dry_run: bool,
+) -> Result<RunReport> {
+    if filter.is_empty() {
+        return Err(Error::InvalidInput(String::from(
+            "Refusing to delete emojis without any selection criteria",
+        )));
+    }
+
+    let existing_emoji_collection = EmojiCollection::from_new_emoji_stream(client.clone()).await?;
+    let mut selected_emojis: Vec<&Emoji> = existing_emoji_collection
+        .iter()
+        .filter(|emoji| filter.matches(emoji))
+        .collect();
+    if selected_emojis.is_empty() {
+        println!("No emojis meet the selection criteria");
+        return Ok(RunReport::new());
+    }
+    // Aliases go first, since they may otherwise be removed along with the emoji they alias
+    selected_emojis
+        .sort_by(|a, b| (a.alias_for.is_empty(), &a.name).cmp(&(b.alias_for.is_empty(), &b.name)));
+    let selected_names: HashSet<&str> = selected_emojis
+        .iter()
+        .map(|emoji| emoji.name.as_str())
+        .collect();
+    let mut removed_aliases: Vec<&Emoji> = existing_emoji_collection
+        .iter()
+        .filter(|emoji| {
+            selected_names.contains(emoji.alias_for.as_str())
+                && !selected_names.contains(emoji.name.as_str())
+        })
+        .collect();
+    removed_aliases.sort_by(|a, b| a.name.cmp(&b.name));
+
+    println!(
+        "{} emoji(s) {} be deleted:",
+        selected_emojis.len(),
+        if dry_run { "would" } else { "will" }
+    );
+    for emoji in &selected_emojis {
+        let alias_description = if emoji.alias_for.is_empty() {
+            String::new()
+        } else {
+            format!(", alias for {}", emoji.alias_for)
+        };
+        println!(
+            "  {} (added by {} on {}{})",
+            emoji.name.yellow(),
+            emoji.added_by,
+            emoji.created.format("%Y-%m-%d"),
+            alias_description
+        );
+    }
+    if !removed_aliases.is_empty() {
+        println!(
+            "{} alias(es) for them {} be deleted along with them:",
+            removed_aliases.len(),
+            if dry_run { "would" } else { "will" }
+        );
+        for alias in &removed_aliases {
+            println!("  {} (alias for {})", alias.name.yellow(), alias.alias_for);
+        }
+    }
+    if dry_run {
+        let mut report = RunReport::new_dry_run();
+        for emoji in selected_emojis.into_iter().chain(removed_aliases) {
+            report.record(&emoji.name, EmojiOutcome::Deleted);
+        }
+        return Ok(report);
+    }
+    if !assume_yes && !confirm("Delete these emojis?")? {
+        println!("Nothing was deleted");
+        return Ok(RunReport::new());
+    }
+
+    let mut report = RunReport::new();
+    for emoji in selected_emojis {
+        match client.remove(&emoji.name).await {
+            Ok(()) => {
+                report.record(&emoji.name, EmojiOutcome::Deleted);
+                for alias in removed_aliases
+                    .iter()
+                    .filter(|alias| alias.alias_for == emoji.name)
+                {
+                    report.record(&alias.name, EmojiOutcome::Deleted);
+                }
+            }
+            Err(e) => {
+                error!("{}; skipping", e);
+                report.record(&emoji.name, EmojiOutcome::Failed(e.to_string()));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn confirm(prompt: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
+
+/// An action taken by `rename` or when overwriting an emoji that is undone if a later action fails
+enum RenameStep {
+    AddedNewEmoji,
+    /// Removed the old emoji, whose image is kept unless it is an alias
+    RemovedOldEmoji(Option<Vec<u8>>),
+    RemovedAlias(String),
+    AddedAlias(String),
+}
+
+/// Renames an emoji by adding it under `new_name`, pointing every alias for it at `new_name`, and then removing it
+/// under `old_name`. If any of those actions fails, the ones already taken are undone. The metadata in
+/// `archive_directory`, if provided, is updated to match; it is read first, so that nothing is renamed if it is not
+/// an archive.
+pub async fn rename<P: AsRef<Path>>(
+    client: Arc<SlackClient>,
+    old_name: &str,
+    new_name: &str,
+    archive_directory: Option<P>,
+) -> Result<()> {
+    let existing_emoji_collection = EmojiCollection::from_new_emoji_stream(client.clone()).await?;
+    let old_emoji = existing_emoji_collection
+        .get(old_name)
+        .ok_or_else(|| Error::InvalidInput(format!("Emoji {} does not exist", old_name)))?;
+    if EMOJI_STANDARD_SHORTCODES.contains::<str>(new_name) {
+        return Err(Error::InvalidInput(format!(
+            "Cannot rename emoji to {} since it is a standard emoji short code",
+            new_name
+        )));
+    }
+    if existing_emoji_collection.get(new_name).is_some() {
+        return Err(Error::NameTaken {
+            name: new_name.to_string(),
+        });
+    }
+
+    let mut aliases: Vec<String> = existing_emoji_collection
+        .iter()
+        .filter(|emoji| emoji.alias_for == old_name)
+        .map(|emoji| emoji.name.clone())
+        .collect();
+    aliases.sort();
+
+    // Read before anything changes on Slack, so that an archive that cannot be updated stops the rename
+    let archive = match archive_directory {
+        Some(archive_directory) => {
+            let emoji_directory = EmojiDirectory::new(archive_directory.as_ref());
+            emoji_directory.ensure_is_directory().await?;
+            if tokio::fs::metadata(emoji_directory.get_metadata_filepath())
+                .await
+                .is_err()
+            {
+                return Err(Error::InvalidInput(format!(
+                    "{} is not an archive of emojis",
+                    emoji_directory.path().display()
+                )));
             }
-            Err(e) => error!("Failed to fetch emoji list or parse response: {}", e),
+            let emoji_files = emoji_directory.read_emoji_files().await?;
+            Some((emoji_directory, emoji_files))
         }
+        None => None,
+    };
+
+    let mut completed_steps = Vec::new();
+    if let Err(e) =
+        perform_rename(&client, old_emoji, new_name, &aliases, &mut completed_steps).await
+    {
+        error!(
+            "Failed to rename emoji {} to {}; rolling back: {}",
+            old_name, new_name, e
+        );
+        roll_back(&client, old_emoji, new_name, completed_steps).await;
+        return Err(e);
+    }
+    info!(
+        "Renamed emoji {} to {} along with {} alias(es)",
+        old_name,
+        new_name,
+        aliases.len()
+    );
+
+    if let Some((emoji_directory, mut emoji_files)) = archive {
+        for emoji_file in &mut emoji_files {
+            if emoji_file.emoji.name == old_name {
+                emoji_file.emoji.name = new_name.to_string();
+            }
+            if emoji_file.emoji.alias_for == old_name {
+                emoji_file.emoji.alias_for = new_name.to_string();
+            }
+        }
+        emoji_directory.rewrite_metadata_file(&emoji_files).await?;
     }
 
     Ok(())
 }
 
-pub async fn upload(client: Rc<SlackClient>, target_directory: &str) -> Result<(), Box<dyn Error>> {
-    let emoji_directory = EmojiDirectory::new(target_directory);
-    match emoji_directory.exists().await {
-        Ok(false) => panic!("\"{}\" is not a directory", target_directory),
-        Err(e) => panic!(
-            "Failed to check existence of directory \"{}\": {}",
-            target_directory, e
-        ),
-        _ => (),
+async fn perform_rename(
+    client: &SlackClient,
+    old_emoji: &Emoji,
+    new_name: &str,
+    aliases: &[String],
+    completed_steps: &mut Vec<RenameStep>,
+) -> Result<()> {
+    if old_emoji.alias_for.is_empty() {
+        let image = client.fetch_image(&old_emoji.url).await?;
+        let filename = EmojiFile::generate_filename_from_url(&old_emoji.url);
+        client.upload_image(new_name, &filename, image).await?;
+    } else {
+        client.add_alias(new_name, &old_emoji.alias_for).await?;
+    }
+    completed_steps.push(RenameStep::AddedNewEmoji);
+
+    for alias in aliases {
+        client.remove(alias).await?;
+        completed_steps.push(RenameStep::RemovedAlias(alias.clone()));
+        client.add_alias(alias, new_name).await?;
+        completed_steps.push(RenameStep::AddedAlias(alias.clone()));
+    }
+
+    client.remove(&old_emoji.name).await
+}
+
+/// Undoes `completed_steps` in reverse, logging any that cannot be undone
+async fn roll_back(
+    client: &SlackClient,
+    old_emoji: &Emoji,
+    new_name: &str,
+    completed_steps: Vec<RenameStep>,
+) {
+    let old_name = &old_emoji.name;
+    for step in completed_steps.into_iter().rev() {
+        let result = match step {
+            RenameStep::AddedNewEmoji => client.remove(new_name).await,
+            RenameStep::RemovedOldEmoji(Some(image)) => {
+                let filename = EmojiFile::generate_filename_from_url(&old_emoji.url);
+                client.upload_image(old_name, &filename, image).await
+            }
+            RenameStep::RemovedOldEmoji(None) => {
+                client.add_alias(old_name, &old_emoji.alias_for).await
+            }
+            RenameStep::RemovedAlias(alias) => client.add_alias(&alias, old_name).await,
+            RenameStep::AddedAlias(alias) => client.remove(&alias).await,
+        };
+        if let Err(e) = result {
+            error!("Failed to roll back changes to emoji {}: {}", old_name, e);
+        }
+    }
+}
+
+/// Copies emojis from the workspace of `source_client` that are missing from the workspace of `dest_client`. Emojis
+/// are downloaded to `archive_directory` on the way, so a directory kept from a previous sync (or download) saves
+/// re-downloading any emoji already in it. As with `download`, emojis that have changed since they were downloaded
+/// are downloaded again as a new revision, and those marked as deleted upstream are no longer marked. Emojis that fail
+/// to download are reported rather than stopping the run.
+pub async fn sync<P: AsRef<Path>>(
+    source_client: Arc<SlackClient>,
+    dest_client: Arc<SlackClient>,
+    archive_directory: P,
+) -> Result<RunReport> {
+    let existing_emoji_collection =
+        EmojiCollection::from_new_emoji_stream(dest_client.clone()).await?;
+
+    let emoji_directory = EmojiDirectory::new(archive_directory.as_ref());
+    emoji_directory.ensure_exists().await?;
+    let mut metadata_file = emoji_directory.open_metadata_file().await?;
+    let downloaded_emoji_files: HashMap<String, EmojiFile> = emoji_directory
+        .read_emoji_files()
+        .await?
+        .into_iter()
+        .map(|emoji_file| (emoji_file.emoji.name.clone(), emoji_file))
+        .collect();
+    let mut revisions = Vec::new();
+    let mut reappeared_names = HashSet::new();
+
+    // Only borrow these in the stream below, since they are needed again afterwards
+    let existing_emoji_collection = &existing_emoji_collection;
+    let emoji_directory = &emoji_directory;
+    let mut failed_downloads = Vec::new();
+    let revisions_mut = &mut revisions;
+    let reappeared_names_mut = &mut reappeared_names;
+    let failed_downloads_mut = &mut failed_downloads;
+
+    let source_stream = new_emoji_stream(source_client.clone(), None);
+    let missing_emoji_files = try_stream! {
+        for await emoji in source_stream {
+            let emoji = emoji?;
+            // Emojis that exist on the destination are passed along without being downloaded, so that they are
+            // reported as skipped
+            let exists_on_destination = !matches!(
+                existing_emoji_collection.get_existence_status(&emoji.name),
+                EmojiExistenceKind::DoesNotExist
+            );
+            if exists_on_destination {
+                yield EmojiFile::from(emoji);
+                continue;
+            }
+
+            let mut emoji_file = match downloaded_emoji_files.get(&emoji.name) {
+                None => EmojiFile::from(emoji),
+                Some(downloaded) if downloaded.has_changed(&emoji) => downloaded.next_revision(emoji),
+                Some(downloaded) => {
+                    if downloaded.deleted_upstream_at.is_some() {
+                        reappeared_names_mut.insert(emoji.name.clone());
+                    }
+                    yield EmojiFile::from(emoji);
+                    continue;
+                }
+            };
+
+            // Left out of the upload, and reported once it is over
+            if let Err(e) = emoji_file
+                .download_to_directory(source_client.clone(), emoji_directory)
+                .await
+            {
+                error!("Failed to download emoji {}: {}", emoji_file.emoji.name, e);
+                failed_downloads_mut.push((emoji_file.emoji.name, e.to_string()));
+                continue;
+            }
+            if emoji_file.revision > 1 {
+                info!(
+                    "Downloaded revision {} of changed emoji: {:?}",
+                    emoji_file.revision, emoji_file
+                );
+                revisions_mut.push(emoji_file.clone());
+            } else {
+                metadata_file.record_emoji(&emoji_file).await?;
+                info!("Downloaded emoji: {:?}", emoji_file);
+            }
+            yield emoji_file;
+        }
     };
 
-    let existing_emoji_collection = EmojiCollection::from_new_emoji_stream(client.clone()).await;
-    let stream = emoji_directory.stream_emoji_files();
-    pin_mut!(stream);
+    let mut report = upload_emoji_files(
+        dest_client,
+        emoji_directory,
+        Some(existing_emoji_collection),
+        missing_emoji_files,
+        None,
+        &ConflictStrategy::Skip,
+        false,
+    )
+    .await?;
+    for (name, reason) in failed_downloads {
+        report.record(name, EmojiOutcome::Failed(reason));
+    }
 
-    let mut aliases_to_process: Vec<EmojiFile> = Vec::new();
+    // Recording revisions and deletions replaces the metadata file, which the stream above appended to
+    if !revisions.is_empty() {
+        emoji_directory.record_revisions(revisions).await?;
+    }
+    if !reappeared_names.is_empty() {
+        emoji_directory
+            .unmark_deleted_upstream(&reappeared_names)
+            .await?;
+    }
+    Ok(report)
+}
 
-    while let Some(Ok(emoji_file)) = stream.next().await {
-        trace!("Determining whether to upload emoji: {:?}", emoji_file);
+/// What `upload_emoji_files` decides to do with an emoji
+enum UploadPlan {
+    Skip(EmojiOutcome),
+    /// Uploads the emoji under `name`, which is its own unless that is taken
+    Upload {
+        name: String,
+    },
+    /// Replaces the emoji of the same name in the workspace, unless it is the same already
+    Overwrite,
+}
+
+/// Decides what to do with `emoji_file` given the emojis that exist in the workspace, without uploading anything
+fn plan_upload(
+    emoji_file: &EmojiFile,
+    existing_emoji_collection: &EmojiCollection,
+    on_conflict: &ConflictStrategy,
+) -> UploadPlan {
+    trace!("Determining whether to upload emoji: {:?}", emoji_file);
+    let name = &emoji_file.emoji.name;
+    let is_standard_shortcode = EMOJI_STANDARD_SHORTCODES.contains::<str>(name);
+    let existence = existing_emoji_collection.get_existence_status(name);
+    if !is_standard_shortcode && matches!(existence, EmojiExistenceKind::DoesNotExist) {
+        return UploadPlan::Upload { name: name.clone() };
+    }
 
-        if EMOJI_STANDARD_SHORTCODES.contains::<str>(&emoji_file.emoji.name) {
+    match on_conflict {
+        ConflictStrategy::Rename { prefix, suffix } => {
+            let new_name = format!("{}{}{}", prefix, name, suffix);
+            if EMOJI_STANDARD_SHORTCODES.contains::<str>(&new_name)
+                || !matches!(
+                    existing_emoji_collection.get_existence_status(&new_name),
+                    EmojiExistenceKind::DoesNotExist
+                )
+            {
+                warn!(
+                    "Cannot upload emoji {} as {}, since that name is taken too; skipping",
+                    name, new_name
+                );
+                UploadPlan::Skip(EmojiOutcome::SkippedExisting)
+            } else {
+                UploadPlan::Upload { name: new_name }
+            }
+        }
+        _ if is_standard_shortcode => {
             warn!(
                 "{}: {}",
                 "Cannot upload emoji due to conflicting Slack short code name (Unicode emoji standard); skipping"
                     .bright_red(),
-                emoji_file.emoji.name.yellow()
+                name.yellow()
             );
-            continue;
+            UploadPlan::Skip(EmojiOutcome::SkippedStandardShortcode)
         }
-
-        match existing_emoji_collection.get_existence_status(&emoji_file.emoji.name) {
-            EmojiExistenceKind::Exists => {
-                trace!("Emoji {} exists on remote; skipping", emoji_file.emoji.name);
-                continue;
-            }
-            EmojiExistenceKind::ExistsAsAliasFor(alias_for) => {
-                trace!(
+        ConflictStrategy::Overwrite => UploadPlan::Overwrite,
+        ConflictStrategy::Skip => {
+            match existence {
+                EmojiExistenceKind::ExistsAsAliasFor(alias_for) => trace!(
                     "Emoji {} exists on remote as an alias for {}; skipping",
-                    emoji_file.emoji.name,
+                    name,
                     alias_for
-                );
-                continue;
+                ),
+                _ => trace!("Emoji {} exists on remote; skipping", name),
             }
-            _ => (),
+            UploadPlan::Skip(EmojiOutcome::SkippedExisting)
         }
+    }
+}
 
-        // Handle aliases later to give a chance for the aliased emoji to be uploaded
-        if !emoji_file.emoji.alias_for.is_empty() {
-            aliases_to_process.push(emoji_file);
-            continue;
+/// Carries out `plan` for `emoji_file`. If `emoji_file` is an alias, it is added as an alias for `alias_for`, which
+/// differs from the name it has in the archive if the emoji it aliases was renamed. In a dry run, nothing is uploaded
+/// or removed, but images are still fetched to find out whether an emoji would be overwritten.
+async fn execute_upload(
+    client: &SlackClient,
+    emoji_directory: &EmojiDirectory,
+    existing_emoji_collection: &EmojiCollection,
+    emoji_file: &EmojiFile,
+    plan: UploadPlan,
+    alias_for: &str,
+    dry_run: bool,
+) -> Result<EmojiOutcome> {
+    let own_name = &emoji_file.emoji.name;
+    match plan {
+        UploadPlan::Skip(outcome) => Ok(outcome),
+        UploadPlan::Upload { name } => {
+            if !dry_run {
+                add_emoji_as(client, emoji_directory, emoji_file, &name, alias_for).await?;
+            }
+            if &name == own_name {
+                Ok(EmojiOutcome::Uploaded)
+            } else {
+                Ok(EmojiOutcome::Renamed(name))
+            }
         }
+        UploadPlan::Overwrite => {
+            let existing_emoji = match existing_emoji_collection.get(own_name) {
+                Some(existing_emoji) => existing_emoji,
+                None => return Ok(EmojiOutcome::SkippedExisting),
+            };
+            if is_same_emoji(
+                client,
+                emoji_directory,
+                emoji_file,
+                alias_for,
+                existing_emoji,
+            )
+            .await?
+            {
+                trace!("Emoji {} is the same on remote; skipping", own_name);
+                return Ok(EmojiOutcome::SkippedExisting);
+            }
+            if dry_run {
+                return Ok(EmojiOutcome::Overwritten);
+            }
 
-        if let Err(e) = emoji_file
-            .upload_from_directory(client.clone(), &emoji_directory)
+            let mut aliases: Vec<String> = existing_emoji_collection
+                .iter()
+                .filter(|emoji| &emoji.alias_for == own_name)
+                .map(|emoji| emoji.name.clone())
+                .collect();
+            aliases.sort();
+
+            let mut completed_steps = Vec::new();
+            if let Err(e) = perform_overwrite(
+                client,
+                emoji_directory,
+                emoji_file,
+                alias_for,
+                existing_emoji,
+                &aliases,
+                &mut completed_steps,
+            )
             .await
-        {
-            error!("{}; skipping", e);
+            {
+                error!(
+                    "Failed to overwrite emoji {}; putting it back: {}",
+                    own_name, e
+                );
+                roll_back(client, existing_emoji, own_name, completed_steps).await;
+                return Err(e);
+            }
+            Ok(EmojiOutcome::Overwritten)
         }
     }
+}
 
-    for alias_file in aliases_to_process {
-        if let Err(e) = client
-            .add_alias(&alias_file.emoji.name, &alias_file.emoji.alias_for)
-            .await
-        {
+/// Replaces `existing_emoji` with `emoji_file` of the same name. Removing an emoji removes the `aliases` for it too,
+/// so they are added back.
+async fn perform_overwrite(
+    client: &SlackClient,
+    emoji_directory: &EmojiDirectory,
+    emoji_file: &EmojiFile,
+    alias_for: &str,
+    existing_emoji: &Emoji,
+    aliases: &[String],
+    completed_steps: &mut Vec<RenameStep>,
+) -> Result<()> {
+    let name = &existing_emoji.name;
+    let existing_image = if existing_emoji.alias_for.is_empty() {
+        Some(client.fetch_image(&existing_emoji.url).await?)
+    } else {
+        None
+    };
+    client.remove(name).await?;
+    completed_steps.extend(aliases.iter().cloned().map(RenameStep::RemovedAlias));
+    completed_steps.push(RenameStep::RemovedOldEmoji(existing_image));
+
+    add_emoji_as(client, emoji_directory, emoji_file, name, alias_for).await?;
+    completed_steps.push(RenameStep::AddedNewEmoji);
+    for alias in aliases {
+        client.add_alias(alias, name).await?;
+        completed_steps.push(RenameStep::AddedAlias(alias.clone()));
+    }
+    Ok(())
+}
+
+/// Adds `emoji_file` to the workspace as `name`, either with its image or as an alias for `alias_for`
+async fn add_emoji_as(
+    client: &SlackClient,
+    emoji_directory: &EmojiDirectory,
+    emoji_file: &EmojiFile,
+    name: &str,
+    alias_for: &str,
+) -> Result<()> {
+    if emoji_file.emoji.alias_for.is_empty() {
+        let image = tokio::fs::read(emoji_directory.get_emoji_filepath(emoji_file)).await?;
+        client.upload_image(name, &emoji_file.filename, image).await
+    } else {
+        client.add_alias(name, alias_for).await
+    }
+}
+
+/// Whether `emoji_file` is the same as `existing_emoji` of the same name: either both aliases for `alias_for`, or both
+/// images with the same contents
+async fn is_same_emoji(
+    client: &SlackClient,
+    emoji_directory: &EmojiDirectory,
+    emoji_file: &EmojiFile,
+    alias_for: &str,
+    existing_emoji: &Emoji,
+) -> Result<bool> {
+    match (
+        emoji_file.emoji.alias_for.is_empty(),
+        existing_emoji.alias_for.is_empty(),
+    ) {
+        (false, false) => Ok(existing_emoji.alias_for == alias_for),
+        (true, true) => {
+            let sha256 = match &emoji_file.sha256 {
+                Some(sha256) => sha256.clone(),
+                None => hex::encode(Sha256::digest(
+                    tokio::fs::read(emoji_directory.get_emoji_filepath(emoji_file)).await?,
+                )),
+            };
+            let existing_image = client.fetch_image(&existing_emoji.url).await?;
+            Ok(hex::encode(Sha256::digest(existing_image)) == sha256)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Turns the result of uploading an emoji into its outcome
+fn upload_outcome(result: Result<EmojiOutcome>) -> EmojiOutcome {
+    match result {
+        Ok(outcome) => outcome,
+        Err(Error::NameTaken { .. }) => EmojiOutcome::SkippedExisting,
+        Err(e) => {
             error!("{}; skipping", e);
+            EmojiOutcome::Failed(e.to_string())
         }
     }
+}
+
+/// Uploads the emojis in `stream` as planned by `plan_upload`, resolving names that are taken with `on_conflict`.
+/// Aliases are added after every image has been uploaded, each for the emoji at the end of its chain of aliases and
+/// after any alias it is for. Aliases for emojis that were uploaded under a new name are added for the new name.
+/// Aliases that cannot be added, since they are for an emoji that is nowhere to be found or are for each other in a
+/// cycle, are reported as failed before anything is uploaded. If `existing_emoji_collection` is `None`, the emojis in
+/// the workspace were not fetched, and aliases for emojis not in `stream` are added as they are.
+///
+/// If `dry_run` is set, emojis that would be uploaded are reported as such without uploading or removing anything.
+async fn upload_emoji_files<S>(
+    client: Arc<SlackClient>,
+    emoji_directory: &EmojiDirectory,
+    existing_emoji_collection: Option<&EmojiCollection>,
+    stream: S,
+    mut journal: Option<&mut UploadJournal>,
+    on_conflict: &ConflictStrategy,
+    dry_run: bool,
+) -> Result<RunReport>
+where
+    S: Stream<Item = Result<EmojiFile>>,
+{
+    pin_mut!(stream);
+
+    let mut report = if dry_run {
+        RunReport::new_dry_run()
+    } else {
+        RunReport::new()
+    };
+    let no_emojis = EmojiCollection::new();
+    let workspace_emojis = existing_emoji_collection.unwrap_or(&no_emojis);
 
+    let mut images_to_process: Vec<(EmojiFile, UploadPlan)> = Vec::new();
+    let mut aliases_to_process: Vec<(EmojiFile, UploadPlan)> = Vec::new();
+    while let Some(emoji_file_result) = stream.next().await {
+        let emoji_file = match emoji_file_result {
+            Ok(emoji_file) => emoji_file,
+            Err(e) => {
+                error!("Failed to read emoji to upload: {}", e);
+                report.record_error(e);
+                continue;
+            }
+        };
+
+        let plan = plan_upload(&emoji_file, workspace_emojis, on_conflict);
+        if !emoji_file.emoji.alias_for.is_empty() && !matches!(plan, UploadPlan::Skip(_)) {
+            aliases_to_process.push((emoji_file, plan));
+        } else {
+            images_to_process.push((emoji_file, plan));
+        }
+    }
+
+    let uploaded_images: HashSet<String> = images_to_process
+        .iter()
+        .filter(|(_, plan)| !matches!(plan, UploadPlan::Skip(_)))
+        .map(|(emoji_file, _)| emoji_file.emoji.name.clone())
+        .collect();
+    let alias_plan = resolve_aliases(
+        aliases_to_process
+            .iter()
+            .map(|(alias_file, _)| &alias_file.emoji),
+        &uploaded_images,
+        existing_emoji_collection,
+        |name| EMOJI_STANDARD_SHORTCODES.contains::<str>(name),
+    );
+    for problem in &alias_plan.problems {
+        error!("Cannot add alias: {}; skipping", problem);
+        for name in problem.names() {
+            record_upload_outcome(
+                &mut report,
+                journal.as_deref_mut(),
+                name.to_string(),
+                EmojiOutcome::Failed(problem.to_string()),
+            )
+            .await?;
+        }
+    }
+
+    // Outcomes of the images being uploaded, which aliases for them depend on
+    let mut image_outcomes: HashMap<String, EmojiOutcome> = HashMap::new();
+    for (emoji_file, plan) in images_to_process {
+        let outcome = upload_outcome(
+            execute_upload(
+                &client,
+                emoji_directory,
+                workspace_emojis,
+                &emoji_file,
+                plan,
+                &emoji_file.emoji.alias_for,
+                dry_run,
+            )
+            .await,
+        );
+        image_outcomes.insert(emoji_file.emoji.name.clone(), outcome.clone());
+        record_upload_outcome(
+            &mut report,
+            journal.as_deref_mut(),
+            emoji_file.emoji.name,
+            outcome,
+        )
+        .await?;
+    }
+
+    let mut aliases_by_name: HashMap<String, (EmojiFile, UploadPlan)> = aliases_to_process
+        .into_iter()
+        .map(|(alias_file, plan)| (alias_file.emoji.name.clone(), (alias_file, plan)))
+        .collect();
+    for ResolvedAlias { name, root } in alias_plan.order {
+        let (alias_file, plan) = match aliases_by_name.remove(&name) {
+            Some(alias) => alias,
+            None => continue,
+        };
+        let alias_for = match root {
+            AliasRoot::Uploaded(image) => match image_outcomes.get(&image) {
+                Some(EmojiOutcome::Renamed(new_name)) => new_name.clone(),
+                Some(EmojiOutcome::Failed(_)) | None => {
+                    let reason =
+                        format!("{} is an alias for {}, which failed to upload", name, image);
+                    error!("Cannot add alias: {}; skipping", reason);
+                    record_upload_outcome(
+                        &mut report,
+                        journal.as_deref_mut(),
+                        name,
+                        EmojiOutcome::Failed(reason),
+                    )
+                    .await?;
+                    continue;
+                }
+                Some(_) => image,
+            },
+            AliasRoot::Existing(emoji) | AliasRoot::Standard(emoji) => emoji,
+        };
+        let outcome = upload_outcome(
+            execute_upload(
+                &client,
+                emoji_directory,
+                workspace_emojis,
+                &alias_file,
+                plan,
+                &alias_for,
+                dry_run,
+            )
+            .await,
+        );
+        record_upload_outcome(&mut report, journal.as_deref_mut(), name, outcome).await?;
+    }
+
+    Ok(report)
+}
+
+/// Records the outcome of uploading an emoji in `report` and, if there is one, in `journal`
+async fn record_upload_outcome(
+    report: &mut RunReport,
+    journal: Option<&mut UploadJournal>,
+    name: String,
+    outcome: EmojiOutcome,
+) -> Result<()> {
+    if let Some(journal) = journal {
+        journal.record(&name, &outcome).await?;
+    }
+    report.record(name, outcome);
     Ok(())
 }
 
@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::emoji::{Emoji, EmojiCollection};
+
+/// The emoji that a chain of aliases ends at, which is what an alias is added for, since Slack cannot alias an alias
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AliasRoot {
+    /// An image uploaded in the same run, which may end up under another name
+    Uploaded(String),
+    /// An emoji already in the workspace
+    Existing(String),
+    /// A standard Unicode emoji
+    Standard(String),
+}
+
+/// Why an alias cannot be added
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AliasProblem {
+    /// The alias is for an emoji that is not being uploaded, not in the workspace and not standard
+    Dangling { name: String, alias_for: String },
+    /// The aliases are for each other, each for the next and the last for the first
+    Cycle { names: Vec<String> },
+    /// The alias is for another alias that cannot be added
+    Broken { name: String, alias_for: String },
+}
+
+impl AliasProblem {
+    /// The aliases that cannot be added because of the problem
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            Self::Dangling { name, .. } | Self::Broken { name, .. } => vec![name.as_str()],
+            Self::Cycle { names } => names.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl fmt::Display for AliasProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dangling { name, alias_for } => write!(
+                f,
+                "{} is an alias for {}, which is not being uploaded, not in the workspace and not a standard emoji",
+                name, alias_for
+            ),
+            Self::Cycle { names } => write!(
+                f,
+                "aliases form a cycle: {} -> {}",
+                names.join(" -> "),
+                names[0]
+            ),
+            Self::Broken { name, alias_for } => write!(
+                f,
+                "{} is an alias for {}, which cannot be added",
+                name, alias_for
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedAlias {
+    pub name: String,
+    pub root: AliasRoot,
+}
+
+/// The aliases to add, each after any alias it is for, and the problems with those that cannot be added
+#[derive(Debug, Default)]
+pub struct AliasPlan {
+    pub order: Vec<ResolvedAlias>,
+    pub problems: Vec<AliasProblem>,
+}
+
+/// Works out what each of `aliases` ends up aliasing, following aliases for other aliases among them and in
+/// `existing_emoji_collection`. `uploaded_images` are the names of images uploaded in the same run, and
+/// `is_standard` tells whether a name is that of a standard emoji. Without the emojis in the workspace, any other name
+/// is assumed to be in it, so dangling aliases are not found. Aliases are resolved in the order given, so that the
+/// order of the plan only differs from it where an alias has to come after the one it is for.
+pub fn resolve_aliases<'a, I, F>(
+    aliases: I,
+    uploaded_images: &HashSet<String>,
+    existing_emoji_collection: Option<&EmojiCollection>,
+    is_standard: F,
+) -> AliasPlan
+where
+    I: IntoIterator<Item = &'a Emoji>,
+    F: Fn(&str) -> bool,
+{
+    let aliases: Vec<&Emoji> = aliases.into_iter().collect();
+    let mut resolver = AliasResolver {
+        alias_for: aliases
+            .iter()
+            .map(|alias| (alias.name.as_str(), alias.alias_for.as_str()))
+            .collect(),
+        uploaded_images,
+        existing_emoji_collection,
+        is_standard,
+        resolved: HashMap::new(),
+        visiting: Vec::new(),
+        plan: AliasPlan::default(),
+    };
+    for alias in aliases {
+        resolver.resolve(&alias.name);
+    }
+    resolver.plan
+}
+
+struct AliasResolver<'a, F> {
+    alias_for: HashMap<&'a str, &'a str>,
+    uploaded_images: &'a HashSet<String>,
+    existing_emoji_collection: Option<&'a EmojiCollection>,
+    is_standard: F,
+    /// The root of each alias resolved so far, or `None` if it cannot be added
+    resolved: HashMap<&'a str, Option<AliasRoot>>,
+    /// The chain of aliases being resolved, each for the next
+    visiting: Vec<&'a str>,
+    plan: AliasPlan,
+}
+
+impl<'a, F: Fn(&str) -> bool> AliasResolver<'a, F> {
+    fn resolve(&mut self, name: &'a str) -> Option<AliasRoot> {
+        if let Some(root) = self.resolved.get(name) {
+            return root.clone();
+        }
+        if let Some(position) = self.visiting.iter().position(|visiting| *visiting == name) {
+            let cycle = self.visiting.split_off(position);
+            for member in &cycle {
+                self.resolved.insert(*member, None);
+            }
+            self.plan.problems.push(AliasProblem::Cycle {
+                names: cycle.iter().map(ToString::to_string).collect(),
+            });
+            // Put back, since every member of the cycle is still being resolved
+            self.visiting.extend(cycle);
+            return None;
+        }
+
+        let alias_for = self.alias_for[name];
+        self.visiting.push(name);
+        let root = if self.alias_for.contains_key(alias_for) {
+            let root = self.resolve(alias_for);
+            if root.is_none() && !self.resolved.contains_key(name) {
+                self.plan.problems.push(AliasProblem::Broken {
+                    name: name.to_string(),
+                    alias_for: alias_for.to_string(),
+                });
+            }
+            root
+        } else {
+            let root = self.resolve_emoji(alias_for);
+            if root.is_none() {
+                self.plan.problems.push(AliasProblem::Dangling {
+                    name: name.to_string(),
+                    alias_for: alias_for.to_string(),
+                });
+            }
+            root
+        };
+        self.visiting.pop();
+
+        // Members of a cycle are resolved to `None` already
+        if !self.resolved.contains_key(name) {
+            self.resolved.insert(name, root.clone());
+            if let Some(root) = &root {
+                self.plan.order.push(ResolvedAlias {
+                    name: name.to_string(),
+                    root: root.clone(),
+                });
+            }
+        }
+        root
+    }
+
+    /// Resolves a name that is not one of the aliases being added
+    fn resolve_emoji(&self, name: &str) -> Option<AliasRoot> {
+        if self.uploaded_images.contains(name) {
+            return Some(AliasRoot::Uploaded(name.to_string()));
+        }
+        if (self.is_standard)(name) {
+            return Some(AliasRoot::Standard(name.to_string()));
+        }
+        let existing_emoji_collection = match self.existing_emoji_collection {
+            Some(existing_emoji_collection) => existing_emoji_collection,
+            None => return Some(AliasRoot::Existing(name.to_string())),
+        };
+        // Aliases in the workspace are for images, since Slack cannot alias an alias
+        existing_emoji_collection.get(name).map(|existing| {
+            AliasRoot::Existing(if existing.alias_for.is_empty() {
+                name.to_string()
+            } else {
+                existing.alias_for.clone()
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    fn emoji(name: &str, alias_for: &str) -> Emoji {
+        Emoji {
+            name: name.to_string(),
+            url: format!("https://emoji.slack-edge.com/T12345/{}/aaa.png", name),
+            added_by: String::from("Jimmy Dean"),
+            alias_for: alias_for.to_string(),
+            created: Utc.timestamp_opt(1595443479, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_aliases() {
+        let aliases = vec![
+            // Listed before the alias it is for
+            emoji("parrot-alias-alias", "parrot-alias"),
+            emoji("parrot-alias", "parrot"),
+            emoji("their-blob-alias", "their-blob-wave"),
+            emoji("thumbs", "+1"),
+            emoji("ghost", "missing"),
+            emoji("ghost-alias", "ghost"),
+            emoji("ping", "pong"),
+            emoji("pong", "ping"),
+            emoji("ping-alias", "ping"),
+        ];
+        let uploaded_images = HashSet::from([String::from("parrot")]);
+        let mut existing_emoji_collection = EmojiCollection::new();
+        existing_emoji_collection.insert(emoji("their-blob", ""));
+        existing_emoji_collection.insert(emoji("their-blob-wave", "their-blob"));
+
+        let plan = resolve_aliases(
+            &aliases,
+            &uploaded_images,
+            Some(&existing_emoji_collection),
+            |name| name == "+1",
+        );
+
+        let resolved = |name: &str, root: AliasRoot| ResolvedAlias {
+            name: name.to_string(),
+            root,
+        };
+        assert_eq!(
+            plan.order,
+            vec![
+                resolved("parrot-alias", AliasRoot::Uploaded(String::from("parrot"))),
+                resolved(
+                    "parrot-alias-alias",
+                    AliasRoot::Uploaded(String::from("parrot"))
+                ),
+                resolved(
+                    "their-blob-alias",
+                    AliasRoot::Existing(String::from("their-blob"))
+                ),
+                resolved("thumbs", AliasRoot::Standard(String::from("+1"))),
+            ]
+        );
+        assert_eq!(
+            plan.problems,
+            vec![
+                AliasProblem::Dangling {
+                    name: String::from("ghost"),
+                    alias_for: String::from("missing"),
+                },
+                AliasProblem::Broken {
+                    name: String::from("ghost-alias"),
+                    alias_for: String::from("ghost"),
+                },
+                AliasProblem::Cycle {
+                    names: vec![String::from("ping"), String::from("pong")],
+                },
+                AliasProblem::Broken {
+                    name: String::from("ping-alias"),
+                    alias_for: String::from("ping"),
+                },
+            ]
+        );
+        assert_eq!(
+            plan.problems[2].to_string(),
+            "aliases form a cycle: ping -> pong -> ping"
+        );
+
+        // Without the emojis in the workspace, an alias for an unknown emoji is assumed to be fine
+        let plan = resolve_aliases(&aliases[4..6], &uploaded_images, None, |_| false);
+        assert!(plan.problems.is_empty());
+        assert_eq!(
+            plan.order,
+            vec![
+                resolved("ghost", AliasRoot::Existing(String::from("missing"))),
+                resolved("ghost-alias", AliasRoot::Existing(String::from("missing"))),
+            ]
+        );
+    }
+}
@@ -1,33 +1,100 @@
-use std::collections::HashSet;
-use std::error::Error;
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::sync::Arc;
 
 use async_stream::try_stream;
+use chrono::prelude::*;
 use futures::stream::Stream;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
-use tokio::fs::{create_dir_all, metadata, File, OpenOptions};
+use serde_json::Value;
+use tokio::fs::{self, create_dir_all, metadata, rename, File, OpenOptions};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 
 use crate::emoji::Emoji;
+use crate::error::{Error, Result};
 use crate::slack::SlackClient;
 
-static EMOJI_METADATA_FILENAME: &str = "metadata.ndjson";
+pub(crate) const EMOJI_METADATA_FILENAME: &str = "metadata.ndjson";
+pub(crate) const MANIFEST_FILENAME: &str = "manifest.json";
+pub(crate) const HISTORY_FILENAME: &str = "history.ndjson";
+/// Added to the name of a file that is written in full before it replaces the file
+pub(crate) const TEMP_FILE_SUFFIX: &str = ".tmp";
+
+/// Upgrades a metadata record from one format version to the next; the migration at index `i` upgrades a record from
+/// version `i + 1` to version `i + 2`. Migrations must leave a record that is already in the newer format unchanged,
+/// since an upgrade that is interrupted after rewriting the metadata file but before updating the manifest is redone.
+type Migration = fn(&mut Value);
+
+const MIGRATIONS: [Migration; 3] = [migrate_v1_to_v2, migrate_v2_to_v3, migrate_v3_to_v4];
+
+/// The format version of metadata records written by this version of the tool
+pub const CURRENT_FORMAT_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+/// Version 2 added the optional `sha256` and `size` fields, which records from version 1 simply lack
+fn migrate_v1_to_v2(_record: &mut Value) {}
+
+/// Version 3 numbers the revisions of each emoji, starting from 1 for every record written before
+fn migrate_v2_to_v3(record: &mut Value) {
+    if let Value::Object(fields) = record {
+        fields.entry("revision").or_insert_with(|| 1.into());
+    }
+}
+
+/// Version 4 added the optional `deleted_upstream_at` field. No record from version 3 was known to be deleted, but
+/// the version is bumped so that older versions of the tool refuse to upload emojis that have been deleted.
+fn migrate_v3_to_v4(_record: &mut Value) {}
+
+/// Parses a metadata record written in `format_version`, upgrading it to the current format first
+pub(crate) fn parse_record(contents: &str, format_version: u32) -> serde_json::Result<EmojiFile> {
+    let mut record: Value = serde_json::from_str(contents)?;
+    for migration in MIGRATIONS
+        .iter()
+        .skip(format_version.saturating_sub(1) as usize)
+    {
+        migration(&mut record);
+    }
+    serde_json::from_value(record)
+}
+
+/// Sidecar file of an `EmojiDirectory` that describes the format of its metadata file. Archives from before the
+/// manifest was introduced have none and are taken to be in format version 1.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format_version: u32,
+}
 
 pub struct EmojiMetadataFile {
+    path: PathBuf,
     handle: File,
+    format_version: u32,
 }
 
 impl EmojiMetadataFile {
-    pub async fn open<P: AsRef<Path>>(path: P) -> io::Result<EmojiMetadataFile> {
+    /// Opens the metadata file at `path`, whose records are in `format_version`, creating it if it does not exist
+    pub async fn open<P: AsRef<Path>>(
+        path: P,
+        format_version: u32,
+    ) -> io::Result<EmojiMetadataFile> {
         Ok(EmojiMetadataFile {
+            path: path.as_ref().to_path_buf(),
             handle: OpenOptions::new()
                 .append(true)
                 .read(true)
                 .create(true)
                 .open(path)
                 .await?,
+            format_version,
+        })
+    }
+
+    /// Parses line number `line` (counting from 1) of the metadata file
+    fn parse_line(&self, line: usize, contents: &str) -> Result<EmojiFile> {
+        parse_record(contents, self.format_version).map_err(|source| Error::CorruptMetadata {
+            path: self.path.clone(),
+            line,
+            source,
         })
     }
 
@@ -39,15 +106,17 @@ impl EmojiMetadataFile {
         Ok(())
     }
 
-    pub async fn get_emoji_name_set(&self) -> Result<HashSet<String>, Box<dyn Error>> {
+    pub async fn get_emoji_name_set(&self) -> Result<HashSet<String>> {
         // TODO: not sure how to do this without cloning since BufReader moves `handle`
         let handle = self.handle.try_clone().await?;
         let reader = BufReader::new(handle);
         let mut lines = reader.lines();
         let mut set = HashSet::new();
+        let mut line_number = 0;
 
         while let Some(line) = lines.next_line().await? {
-            let emoji_file: EmojiFile = serde_json::from_str(&line)?;
+            line_number += 1;
+            let emoji_file = self.parse_line(line_number, &line)?;
             set.insert(emoji_file.emoji.name);
         }
 
@@ -55,7 +124,7 @@ impl EmojiMetadataFile {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EmojiDirectory {
     path: PathBuf,
 }
@@ -68,16 +137,30 @@ impl EmojiDirectory {
         Self { path: path.into() }
     }
 
-    pub async fn ensure_exists(&self) {
-        create_dir_all(&self.path)
-            .await
-            .unwrap_or_else(|e| panic!("Could not create EmojiDirectory {:?}: {}", &self, e))
+    pub async fn ensure_exists(&self) -> Result<()> {
+        Ok(create_dir_all(&self.path).await?)
     }
 
-    pub async fn exists(&self) -> Result<bool, Box<dyn Error>> {
+    pub async fn exists(&self) -> Result<bool> {
         Ok(metadata(&self.path).await?.is_dir())
     }
 
+    /// Fails unless the directory exists, e.g. before reading an archive that should have been downloaded already
+    pub async fn ensure_is_directory(&self) -> Result<()> {
+        match self.exists().await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::NotADirectory(self.path.clone())),
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                Err(Error::NotADirectory(self.path.clone()))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     pub fn get_inner_filepath<P: AsRef<Path>>(&self, path: P) -> PathBuf {
         self.path.join(path)
     }
@@ -90,52 +173,297 @@ impl EmojiDirectory {
         self.get_inner_filepath(&emoji_file.filename)
     }
 
-    pub async fn open_metadata_file(&self) -> io::Result<EmojiMetadataFile> {
-        EmojiMetadataFile::open(self.get_metadata_filepath()).await
+    pub fn get_history_filepath(&self) -> PathBuf {
+        self.get_inner_filepath(HISTORY_FILENAME)
+    }
+
+    pub fn get_manifest_filepath(&self) -> PathBuf {
+        self.get_inner_filepath(MANIFEST_FILENAME)
+    }
+
+    /// Reads the format version of the metadata file from the manifest, failing if it is newer than this version of
+    /// the tool understands
+    pub async fn read_format_version(&self) -> Result<u32> {
+        let manifest_filepath = self.get_manifest_filepath();
+        let format_version = match fs::read(&manifest_filepath).await {
+            Ok(contents) => {
+                let manifest: Manifest =
+                    serde_json::from_slice(&contents).map_err(|source| Error::CorruptMetadata {
+                        path: manifest_filepath.clone(),
+                        line: 1,
+                        source,
+                    })?;
+                manifest.format_version
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 1,
+            Err(e) => return Err(e.into()),
+        };
+
+        if format_version > CURRENT_FORMAT_VERSION {
+            return Err(Error::UnsupportedFormatVersion {
+                path: manifest_filepath,
+                found: format_version,
+                supported: CURRENT_FORMAT_VERSION,
+            });
+        }
+        Ok(format_version)
+    }
+
+    async fn write_manifest(&self) -> io::Result<()> {
+        let manifest = Manifest {
+            format_version: CURRENT_FORMAT_VERSION,
+        };
+        let temp_filepath =
+            self.get_inner_filepath(format!("{}{}", MANIFEST_FILENAME, TEMP_FILE_SUFFIX));
+        let mut temp_file = File::create(&temp_filepath).await?;
+        temp_file
+            .write_all(&serde_json::to_vec_pretty(&manifest)?)
+            .await?;
+        temp_file.sync_all().await?;
+        rename(&temp_filepath, self.get_manifest_filepath()).await
+    }
+
+    /// Rewrites the metadata file in the current format if it is in an older one
+    pub async fn upgrade(&self) -> Result<()> {
+        let format_version = self.read_format_version().await?;
+        if format_version == CURRENT_FORMAT_VERSION {
+            return Ok(());
+        }
+
+        let history = self.read_history().await?;
+        if !history.is_empty() {
+            self.rewrite_records(HISTORY_FILENAME, &history).await?;
+        }
+        let emoji_files = self.read_emoji_files().await?;
+        if !emoji_files.is_empty() {
+            info!(
+                "Upgrading metadata in {} from format version {} to {}",
+                self.path.display(),
+                format_version,
+                CURRENT_FORMAT_VERSION
+            );
+        }
+        Ok(self.rewrite_metadata_file(&emoji_files).await?)
+    }
+
+    /// Opens the metadata file for appending records to, upgrading it to the current format first so that the
+    /// records appended are in the same format as the rest
+    pub async fn open_metadata_file(&self) -> Result<EmojiMetadataFile> {
+        self.upgrade().await?;
+        Ok(EmojiMetadataFile::open(self.get_metadata_filepath(), CURRENT_FORMAT_VERSION).await?)
+    }
+
+    /// Reads every record in the metadata file into memory, upgrading each to the current format. An archive that
+    /// does not exist yet has no records.
+    pub async fn read_emoji_files(&self) -> Result<Vec<EmojiFile>> {
+        self.read_records(EMOJI_METADATA_FILENAME).await
+    }
+
+    /// Reads every record superseded by a newer revision of the same emoji, oldest first
+    pub async fn read_history(&self) -> Result<Vec<EmojiFile>> {
+        self.read_records(HISTORY_FILENAME).await
+    }
+
+    /// Reads the records in `filename`, of which there are none if it does not exist
+    async fn read_records(&self, filename: &str) -> Result<Vec<EmojiFile>> {
+        let format_version = self.read_format_version().await?;
+        let filepath = self.get_inner_filepath(filename);
+        if !filepath.exists() {
+            return Ok(Vec::new());
+        }
+        let records_file = EmojiMetadataFile::open(filepath, format_version).await?;
+        let mut lines = BufReader::new(records_file.handle.try_clone().await?).lines();
+        let mut emoji_files = Vec::new();
+
+        while let Some(line) = lines.next_line().await? {
+            emoji_files.push(records_file.parse_line(emoji_files.len() + 1, &line)?);
+        }
+
+        Ok(emoji_files)
+    }
+
+    /// Replaces the contents of the metadata file with `emoji_files`, in the current format. The new contents are
+    /// written to a temporary file first and then renamed over the metadata file, so the metadata file is never left
+    /// partially written.
+    pub async fn rewrite_metadata_file(&self, emoji_files: &[EmojiFile]) -> io::Result<()> {
+        self.rewrite_records(EMOJI_METADATA_FILENAME, emoji_files)
+            .await?;
+        self.write_manifest().await
+    }
+
+    async fn rewrite_records(&self, filename: &str, emoji_files: &[EmojiFile]) -> io::Result<()> {
+        let temp_filepath = self.get_inner_filepath(format!("{}{}", filename, TEMP_FILE_SUFFIX));
+        let mut temp_file = File::create(&temp_filepath).await?;
+        for emoji_file in emoji_files {
+            let mut emoji_bytes = serde_json::to_vec(emoji_file)?;
+            emoji_bytes.extend_from_slice(b"\n");
+            temp_file.write_all(&emoji_bytes).await?;
+        }
+        temp_file.sync_all().await?;
+        rename(&temp_filepath, self.get_inner_filepath(filename)).await
+    }
+
+    /// Replaces the records of the emojis in `revisions` with them, moving the records they supersede to the history
+    /// file. The images of superseded records are left in place, so earlier revisions can still be restored.
+    ///
+    /// The metadata file is replaced, so any `EmojiMetadataFile` opened for appending to it beforehand must not be
+    /// used afterwards.
+    pub async fn record_revisions(&self, revisions: Vec<EmojiFile>) -> Result<()> {
+        let mut revisions: HashMap<String, EmojiFile> = revisions
+            .into_iter()
+            .map(|emoji_file| (emoji_file.emoji.name.clone(), emoji_file))
+            .collect();
+        let mut emoji_files = Vec::new();
+        let mut superseded = Vec::new();
+        for emoji_file in self.read_emoji_files().await? {
+            match revisions.remove(&emoji_file.emoji.name) {
+                Some(revision) => {
+                    superseded.push(emoji_file);
+                    emoji_files.push(revision);
+                }
+                None => emoji_files.push(emoji_file),
+            }
+        }
+        // Emojis with no record to supersede are simply new
+        emoji_files.extend(revisions.into_values());
+
+        self.append_history(&superseded).await?;
+        Ok(self.rewrite_metadata_file(&emoji_files).await?)
     }
 
-    pub fn stream_emoji_files(&self) -> impl Stream<Item = Result<EmojiFile, Box<dyn Error + '_>>> {
+    /// Appends `emoji_files` to the history file. The archive must already be in the current format.
+    pub(crate) async fn append_history(&self, emoji_files: &[EmojiFile]) -> Result<()> {
+        let mut history_file =
+            EmojiMetadataFile::open(self.get_history_filepath(), CURRENT_FORMAT_VERSION).await?;
+        for emoji_file in emoji_files {
+            history_file.record_emoji(emoji_file).await?;
+        }
+        Ok(())
+    }
+
+    /// Marks the emojis called `names` as deleted upstream at `deleted_at`. As with `record_revisions`, any
+    /// `EmojiMetadataFile` opened for appending beforehand must not be used afterwards.
+    pub async fn mark_deleted_upstream(
+        &self,
+        names: &HashSet<String>,
+        deleted_at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.set_deleted_upstream_at(names, Some(deleted_at)).await
+    }
+
+    /// Undoes `mark_deleted_upstream` for the emojis called `names`, which turned out to still exist upstream
+    pub async fn unmark_deleted_upstream(&self, names: &HashSet<String>) -> Result<()> {
+        self.set_deleted_upstream_at(names, None).await
+    }
+
+    async fn set_deleted_upstream_at(
+        &self,
+        names: &HashSet<String>,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let mut emoji_files = self.read_emoji_files().await?;
+        for emoji_file in &mut emoji_files {
+            if names.contains(&emoji_file.emoji.name) {
+                emoji_file.deleted_upstream_at = deleted_at;
+            }
+        }
+        Ok(self.rewrite_metadata_file(&emoji_files).await?)
+    }
+
+    /// Streams the records in the metadata file, upgrading each to the current format. The stream does not borrow
+    /// `self`, so it can be moved to another task.
+    pub fn stream_emoji_files(
+        &self,
+    ) -> impl Stream<Item = Result<EmojiFile>> + Send + Sync + 'static {
+        let directory = self.clone();
         try_stream! {
-            let reader = BufReader::new(self.open_metadata_file().await?.handle);
-            let mut lines = reader.lines();
+            let metadata_file = EmojiMetadataFile::open(
+                directory.get_metadata_filepath(),
+                directory.read_format_version().await?,
+            )
+            .await?;
+            let mut lines = BufReader::new(metadata_file.handle.try_clone().await?).lines();
+            let mut line_number = 0;
 
             while let Some(line) = lines.next_line().await? {
-                let emoji_file: EmojiFile = serde_json::from_str(&line)?;
-                yield emoji_file;
+                line_number += 1;
+                yield metadata_file.parse_line(line_number, &line)?;
             }
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmojiFile {
     #[serde(flatten)]
     pub emoji: Emoji,
     pub filename: String,
+    /// Hex-encoded SHA-256 hash of the image, recorded when it was downloaded. Missing for records written before
+    /// hashes were recorded and for aliases whose image could not be downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Size of the image in bytes; missing whenever `sha256` is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Counts from 1 and goes up each time the emoji is found to have changed upstream since it was downloaded
+    pub revision: u32,
+    /// When a download first found the emoji missing from the workspace, if it has been deleted there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_upstream_at: Option<DateTime<Utc>>,
 }
 
 impl EmojiFile {
-    fn generate_filename_from_url<S: Into<String>>(url: S) -> String {
+    pub fn generate_filename_from_url<S: Into<String>>(url: S) -> String {
         let url = url.into();
         let filename_parts: Vec<&str> = url.rsplitn(3, '/').take(2).collect();
         format!("{}-{}", filename_parts[1], filename_parts[0])
     }
 
+    /// Whether `emoji` differs from the emoji this was downloaded from, i.e. it was deleted and re-created under the
+    /// same name. The URL contains a hash of the image, so a new image always shows up as a new URL.
+    pub fn has_changed(&self, emoji: &Emoji) -> bool {
+        self.emoji.url != emoji.url || self.emoji.created != emoji.created
+    }
+
+    /// A record of `emoji` that supersedes this one, still to be downloaded
+    pub fn next_revision(&self, emoji: Emoji) -> Self {
+        Self {
+            revision: self.revision + 1,
+            ..Self::from(emoji)
+        }
+    }
+
+    /// Downloads the image of the emoji into `directory` and records its hash and size. An alias is added by name
+    /// rather than uploaded with its image, so one whose image cannot be downloaded, such as an alias for a standard
+    /// emoji, is left without it instead of failing.
     pub async fn download_to_directory(
-        &self,
-        client: Rc<SlackClient>,
+        &mut self,
+        client: Arc<SlackClient>,
         directory: &EmojiDirectory,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<()> {
         let emoji_filepath = directory.get_inner_filepath(&self.filename);
-        client.download(&self.emoji.url, &emoji_filepath).await?;
-        Ok(())
+        match client.download(&self.emoji.url, &emoji_filepath).await {
+            Ok(downloaded_image) => {
+                self.sha256 = Some(downloaded_image.sha256);
+                self.size = Some(downloaded_image.size);
+                Ok(())
+            }
+            Err(e) if !self.emoji.alias_for.is_empty() => {
+                warn!(
+                    "Could not download the image of alias {} for {}; recording it without one: {}",
+                    self.emoji.name, self.emoji.alias_for, e
+                );
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
     }
 
     pub async fn upload_from_directory(
         &self,
-        client: Rc<SlackClient>,
+        client: Arc<SlackClient>,
         directory: &EmojiDirectory,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<()> {
         client
             .upload(self, directory.get_emoji_filepath(self))
             .await
@@ -147,6 +475,10 @@ impl From<Emoji> for EmojiFile {
         Self {
             filename: Self::generate_filename_from_url(&emoji.url),
             emoji,
+            sha256: None,
+            size: None,
+            revision: 1,
+            deleted_upstream_at: None,
         }
     }
 }
@@ -156,6 +488,78 @@ impl From<Emoji> for EmojiFile {
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_corrupt_metadata_line_is_reported() {
+        let temp_directory = tempfile::tempdir().unwrap();
+        let emoji_directory = EmojiDirectory::new(temp_directory.path());
+        tokio::fs::write(
+            emoji_directory.get_metadata_filepath(),
+            concat!(
+                r#"{"name":"zuck","url":"https://emoji.slack-edge.com/T03C6/zuck/6f28.png","added_by":"Jimmy Dean","alias_for":"","created":"2020-07-22T18:44:39Z","filename":"zuck-6f28.png"}"#,
+                "\n{\"name\":\n"
+            ),
+        )
+        .await
+        .unwrap();
+
+        match emoji_directory.read_emoji_files().await {
+            Err(Error::CorruptMetadata { line, .. }) => assert_eq!(line, 2),
+            other => panic!("Expected corrupt metadata error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_legacy_metadata_is_upgraded() {
+        let temp_directory = tempfile::tempdir().unwrap();
+        let emoji_directory = EmojiDirectory::new(temp_directory.path());
+        tokio::fs::write(
+            emoji_directory.get_metadata_filepath(),
+            concat!(
+                r#"{"name":"zuck","url":"https://emoji.slack-edge.com/T03C6/zuck/6f28.png","added_by":"Jimmy Dean","alias_for":"","created":"2020-07-22T18:44:39Z","filename":"zuck-6f28.png"}"#,
+                "\n"
+            ),
+        )
+        .await
+        .unwrap();
+
+        // Reading an archive without a manifest leaves it as it is
+        assert_eq!(emoji_directory.read_format_version().await.unwrap(), 1);
+        assert_eq!(emoji_directory.read_emoji_files().await.unwrap().len(), 1);
+        assert!(!emoji_directory.get_manifest_filepath().exists());
+
+        // Opening it for writing upgrades it
+        emoji_directory.open_metadata_file().await.unwrap();
+        assert_eq!(
+            emoji_directory.read_format_version().await.unwrap(),
+            CURRENT_FORMAT_VERSION
+        );
+        let emoji_files = emoji_directory.read_emoji_files().await.unwrap();
+        assert_eq!(emoji_files.len(), 1);
+        assert_eq!(emoji_files[0].emoji.name, "zuck");
+        assert_eq!(emoji_files[0].revision, 1);
+    }
+
+    #[tokio::test]
+    async fn test_newer_format_version_is_rejected() {
+        let temp_directory = tempfile::tempdir().unwrap();
+        let emoji_directory = EmojiDirectory::new(temp_directory.path());
+        tokio::fs::write(
+            emoji_directory.get_manifest_filepath(),
+            format!(r#"{{"format_version":{}}}"#, CURRENT_FORMAT_VERSION + 1),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            emoji_directory.read_emoji_files().await,
+            Err(Error::UnsupportedFormatVersion { found, .. }) if found == CURRENT_FORMAT_VERSION + 1
+        ));
+        assert!(matches!(
+            emoji_directory.open_metadata_file().await,
+            Err(Error::UnsupportedFormatVersion { .. })
+        ));
+    }
+
     #[test]
     fn test_generate_filename_from_url() {
         assert_eq!(
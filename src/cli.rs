@@ -1,56 +1,162 @@
-use crate::emoji::{EmojiStreamParameters, DEFAULT_NUM_EMOJIS_PER_PAGE, DEFAULT_STARTING_PAGE};
-use crate::slack::SlackClient;
-use clap::{ArgAction, Args, Parser, Subcommand};
+use chrono::prelude::*;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
 use env_logger::Env;
+use glob::Pattern;
 use log::LevelFilter;
-use std::rc::Rc;
+use regex::Regex;
+use reqwest::Url;
+use slack_emoji::actions::{
+    ConflictStrategy, DEFAULT_DOWNLOAD_CONCURRENCY, DEFAULT_REFETCH_AFTER_MINS,
+};
+use slack_emoji::emoji::{
+    EmojiStreamParameters, DEFAULT_NUM_EMOJIS_PER_PAGE, DEFAULT_STARTING_PAGE,
+};
+use slack_emoji::error::{Error, Result};
+use slack_emoji::filter::{parse_datetime, parse_names, AliasSelection, EmojiFilter, NamePattern};
+use slack_emoji::retry::{RetryPolicy, DEFAULT_MAX_ATTEMPTS, DEFAULT_MAX_ELAPSED_SECS};
+use slack_emoji::slack::SlackClient;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 pub struct Opts {
-    /// Slack workspace subdomain (e.g. if your Slack is at myorg.slack.com, enter "myorg")
+    /// Slack workspace subdomain (e.g. if your Slack is at myorg.slack.com, enter "myorg"). Required by every
+    /// subcommand that talks to a single workspace.
     #[clap(name = "SLACK WORKSPACE")]
-    workspace: String,
-    /// Path to directory to either download emojis to or upload emojis from. The `download` subcommand will attempt
-    /// to create a directory at the provided path if one does not exist. The `upload` subcommand expects
-    /// that the provided path is an existing directory containing a well-formed 'metadata.ndjson' and emoji files.
+    workspace: Option<String>,
+    /// Path to directory to either download emojis to, upload emojis from or compare against. The `download`
+    /// subcommand will attempt to create a directory at the provided path if one does not exist. The `upload` and
+    /// `diff` subcommands expect that the provided path is an existing directory containing a well-formed
+    /// 'metadata.ndjson' and emoji files.
     #[clap(name = "TARGET DIRECTORY")]
-    pub target_directory: String,
+    target_directory: Option<String>,
     /// Token for a user that has permissions for Slack's administrator-level emoji endpoints.
     /// The token can be manually acquired by inspecting the payload for a request, such as POST /api/emoji.adminList,
     /// via a browser's network dev tools when accessing a Slack workspace's customize/emoji page.
     /// The token generally starts with "xox".
     ///
     /// It is STRONGLY advised to provide this argument via the environment variable SLACK_TOKEN.
-    #[clap(
-        name = "slack token",
-        short = 't',
-        long = "token",
-        env = "SLACK_TOKEN",
-        required = true
-    )]
-    token: String,
+    #[clap(name = "slack token", short = 't', long = "token", env = "SLACK_TOKEN")]
+    token: Option<String>,
     /// It is STRONGLY advised to provide this argument via the environment variable SLACK_SESSION_COOKIE.
     #[clap(
         name = "slack session cookie",
         short = 'd',
         long = "session_cookie",
-        env = "SLACK_SESSION_COOKIE",
-        required = true
+        env = "SLACK_SESSION_COOKIE"
     )]
-    session_cookie: String,
+    session_cookie: Option<String>,
+    /// Overrides the base URL that Slack API requests are sent to, which is otherwise derived from SLACK WORKSPACE
+    /// (e.g. "http://localhost:8080/api" to target a local stand-in for Slack)
+    #[clap(long, env = "SLACK_API_BASE_URL")]
+    api_base_url: Option<String>,
+    /// Overrides the scheme, host and port that emoji images are downloaded from, which are otherwise taken from the
+    /// image URLs returned by Slack. Any path given is prepended to the image URL's path.
+    #[clap(long, env = "SLACK_EMOJI_CDN_URL")]
+    emoji_cdn_url: Option<Url>,
     /// Sets the log level based on occurrences. The default log level includes ERROR and WARN messages. One occurrence
     /// includes INFO messages, two occurrences include DEBUG messages, and three or more occurrences include TRACE
     /// messages. The log level can also be set via the environment variable SLACK_EMOJI_LOG_LEVEL. This argument, if
     /// provided, takes precedence over the aforementioned environment variable.
-    #[clap(name = "verbose", short, action(ArgAction::Count))]
+    #[clap(name = "verbose", short, action(ArgAction::Count), global = true)]
     verbosity: u8,
-    // #[clap(long, required = false)]
-    // filter_by_uploader: Option<String>,
+    #[clap(flatten)]
+    retry_opts: RetryOpts,
     #[clap(subcommand)]
     pub subcommand: SubCommandKind,
 }
 
+/// A workspace to connect to, along with the credentials for it
+#[derive(Clone)]
+pub struct WorkspaceOpts {
+    workspace: String,
+    token: String,
+    session_cookie: String,
+    api_base_url: Option<String>,
+    emoji_cdn_url: Option<Url>,
+}
+
+/// Same as `WorkspaceOpts`, but for a pair of workspaces, each with its own credentials
+#[derive(Args)]
+pub struct WorkspacePairOpts {
+    /// Slack workspace subdomain to copy emojis from
+    #[clap(name = "SOURCE WORKSPACE")]
+    source_workspace: String,
+    /// Slack workspace subdomain to copy emojis to
+    #[clap(name = "DEST WORKSPACE")]
+    dest_workspace: String,
+    /// Token for SOURCE WORKSPACE; see `--token`.
+    ///
+    /// It is STRONGLY advised to provide this argument via the environment variable SLACK_SOURCE_TOKEN.
+    #[clap(long, env = "SLACK_SOURCE_TOKEN", required = true)]
+    source_token: String,
+    /// It is STRONGLY advised to provide this argument via the environment variable SLACK_SOURCE_SESSION_COOKIE.
+    #[clap(long, env = "SLACK_SOURCE_SESSION_COOKIE", required = true)]
+    source_session_cookie: String,
+    /// Token for DEST WORKSPACE; see `--token`.
+    ///
+    /// It is STRONGLY advised to provide this argument via the environment variable SLACK_DEST_TOKEN.
+    #[clap(long, env = "SLACK_DEST_TOKEN", required = true)]
+    dest_token: String,
+    /// It is STRONGLY advised to provide this argument via the environment variable SLACK_DEST_SESSION_COOKIE.
+    #[clap(long, env = "SLACK_DEST_SESSION_COOKIE", required = true)]
+    dest_session_cookie: String,
+    /// Overrides the base URL that Slack API requests for SOURCE WORKSPACE are sent to
+    #[clap(long, env = "SLACK_SOURCE_API_BASE_URL")]
+    source_api_base_url: Option<String>,
+    /// Overrides the scheme, host and port that emoji images of SOURCE WORKSPACE are downloaded from
+    #[clap(long, env = "SLACK_SOURCE_EMOJI_CDN_URL")]
+    source_emoji_cdn_url: Option<Url>,
+    /// Overrides the base URL that Slack API requests for DEST WORKSPACE are sent to
+    #[clap(long, env = "SLACK_DEST_API_BASE_URL")]
+    dest_api_base_url: Option<String>,
+}
+
+impl WorkspacePairOpts {
+    pub fn source(&self) -> WorkspaceOpts {
+        WorkspaceOpts {
+            workspace: self.source_workspace.clone(),
+            token: self.source_token.clone(),
+            session_cookie: self.source_session_cookie.clone(),
+            api_base_url: self.source_api_base_url.clone(),
+            emoji_cdn_url: self.source_emoji_cdn_url.clone(),
+        }
+    }
+
+    pub fn dest(&self) -> WorkspaceOpts {
+        WorkspaceOpts {
+            workspace: self.dest_workspace.clone(),
+            token: self.dest_token.clone(),
+            session_cookie: self.dest_session_cookie.clone(),
+            api_base_url: self.dest_api_base_url.clone(),
+            emoji_cdn_url: None,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct RetryOpts {
+    /// Maximum number of times a single request to Slack is sent before giving up. Rate-limited requests are retried
+    /// after the duration Slack asks for; server and connection errors are retried with exponential backoff.
+    #[clap(
+        long,
+        env = "SLACK_EMOJI_MAX_ATTEMPTS",
+        default_value_t = DEFAULT_MAX_ATTEMPTS,
+        global = true
+    )]
+    max_attempts: u32,
+    /// Maximum number of seconds to spend retrying a single request to Slack, including time spent waiting
+    #[clap(
+        long,
+        env = "SLACK_EMOJI_RETRY_BUDGET_SECS",
+        default_value_t = DEFAULT_MAX_ELAPSED_SECS,
+        global = true
+    )]
+    retry_budget_secs: u64,
+}
+
 #[derive(Args)]
 pub struct EmojiStreamOpts {
     #[clap(long, required = false, default_value_t = DEFAULT_STARTING_PAGE)]
@@ -61,20 +167,186 @@ pub struct EmojiStreamOpts {
     limit_num_pages: Option<u16>,
 }
 
+#[derive(Args)]
+pub struct EmojiFilterOpts {
+    /// Selects the emoji with exactly this name; can be provided multiple times
+    #[clap(long = "name")]
+    names: Vec<String>,
+    /// Selects the emojis named in this file, one per line; lines starting with '#' are skipped. Combines with --name.
+    #[clap(long, value_parser = read_names_file)]
+    names_file: Option<HashSet<String>>,
+    /// Selects emojis whose names match this glob pattern (e.g. "party*")
+    #[clap(long)]
+    name_glob: Option<Pattern>,
+    /// Selects emojis whose names match this regular expression (e.g. "^blob-")
+    #[clap(long)]
+    name_regex: Option<Regex>,
+    /// Selects emojis uploaded by the user with this display name
+    #[clap(long)]
+    added_by: Option<String>,
+    /// Selects emojis created after this date (e.g. "2022-01-31", meaning midnight UTC) or RFC 3339 date and time
+    #[clap(long, value_parser = parse_datetime)]
+    created_after: Option<DateTime<Utc>>,
+    /// Selects emojis created before this date (e.g. "2022-01-31", meaning midnight UTC) or RFC 3339 date and time
+    #[clap(long, value_parser = parse_datetime)]
+    created_before: Option<DateTime<Utc>>,
+    /// Selects only aliases
+    #[clap(long, conflicts_with = "no_aliases")]
+    aliases_only: bool,
+    /// Selects only emojis that are not aliases
+    #[clap(long)]
+    no_aliases: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OnConflict {
+    Skip,
+    Rename,
+    Overwrite,
+}
+
+#[derive(Args)]
+pub struct ConflictOpts {
+    /// What to do with an emoji whose name is taken in SLACK WORKSPACE or by a standard emoji: leave it out, upload it
+    /// under a name with --rename-prefix and --rename-suffix added, or replace the emoji in SLACK WORKSPACE unless it
+    /// is the same
+    #[clap(long, value_enum, default_value_t = OnConflict::Skip)]
+    on_conflict: OnConflict,
+    /// With --on-conflict rename, added to the start of the names of emojis whose names are taken
+    #[clap(long, default_value = "")]
+    rename_prefix: String,
+    /// With --on-conflict rename, added to the end of the names of emojis whose names are taken
+    #[clap(long, default_value = "")]
+    rename_suffix: String,
+}
+
 #[derive(Subcommand)]
 pub enum SubCommandKind {
-    /// Downloads emojis from SLACK WORKSPACE to TARGET DIRECTORY
+    /// Downloads emojis from SLACK WORKSPACE to TARGET DIRECTORY, limited to those that meet all of the given selection
+    /// criteria, if any
     Download {
         #[clap(flatten)]
         emoji_stream_opts: EmojiStreamOpts,
+        #[clap(flatten)]
+        emoji_filter_opts: EmojiFilterOpts,
+        /// Maximum number of emoji images to download at once
+        #[clap(long, default_value_t = DEFAULT_DOWNLOAD_CONCURRENCY)]
+        concurrency: usize,
+        /// Marks emojis in TARGET DIRECTORY that are no longer in SLACK WORKSPACE as deleted upstream. Only done if
+        /// every page of emojis was listed without errors.
+        #[clap(long)]
+        mark_deleted: bool,
+        /// Reports what would be downloaded (and marked as deleted) without writing anything to TARGET DIRECTORY
+        #[clap(long)]
+        dry_run: bool,
+        /// Path to write a JSON report of the outcome for each emoji to, in addition to the summary printed at the end
+        #[clap(long)]
+        report_json: Option<String>,
+    },
+    /// Uploads emojis to SLACK WORKSPACE from TARGET DIRECTORY, limited to those that meet all of the given selection
+    /// criteria, if any
+    Upload {
+        #[clap(flatten)]
+        emoji_filter_opts: EmojiFilterOpts,
+        /// Path to write a JSON report of the outcome for each emoji to, in addition to the summary printed at the end
+        #[clap(long)]
+        report_json: Option<String>,
+        /// Continues from where an earlier upload from TARGET DIRECTORY stopped, according to the journal it left in
+        /// 'upload-journal.ndjson'. Emojis that failed then are tried again.
+        #[clap(long)]
+        resume: bool,
+        /// When resuming, the emojis in SLACK WORKSPACE are only fetched again to work out which to skip if the journal
+        /// was last written to more than this many minutes ago
+        #[clap(long, requires = "resume", default_value_t = DEFAULT_REFETCH_AFTER_MINS)]
+        refetch_after_mins: u64,
+        /// Also uploads emojis that a download marked as deleted upstream
+        #[clap(long)]
+        include_deleted: bool,
+        #[clap(flatten)]
+        conflict_opts: ConflictOpts,
+        /// Reports what would be uploaded without uploading anything or writing the upload journal
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Shows how the emojis in TARGET DIRECTORY differ from those in SLACK WORKSPACE
+    Diff {
+        /// Prints the differences as JSON instead of as human-readable text
+        #[clap(long)]
+        json: bool,
+    },
+    /// Checks TARGET DIRECTORY for missing or altered images, images without metadata, and malformed or duplicate
+    /// lines of metadata
+    Verify {
+        /// Path to an existing directory containing a 'metadata.ndjson'
+        #[clap(name = "TARGET DIRECTORY")]
+        target_directory: String,
+        /// Prints the problems found as JSON instead of as human-readable text
+        #[clap(long)]
+        json: bool,
+    },
+    /// Rewrites the metadata in TARGET DIRECTORY with one record per emoji, sorted by name. Of several records for the
+    /// same emoji, the one with the highest revision is kept, then the one created last, then the one furthest down.
+    /// Records whose image is missing are left out.
+    Compact {
+        /// Path to an existing directory containing a 'metadata.ndjson'
+        #[clap(name = "TARGET DIRECTORY")]
+        target_directory: String,
+    },
+    /// Deletes the emojis in SLACK WORKSPACE that meet all of the given selection criteria
+    Delete {
+        #[clap(flatten)]
+        emoji_filter_opts: EmojiFilterOpts,
+        /// Deletes the selected emojis without asking for confirmation first
+        #[clap(short, long)]
+        yes: bool,
+        /// Lists the emojis that would be deleted without deleting them
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Renames emoji OLD NAME in SLACK WORKSPACE to NEW NAME, keeping any aliases for it working
+    Rename {
+        #[clap(name = "OLD NAME")]
+        old_name: String,
+        #[clap(name = "NEW NAME")]
+        new_name: String,
+        /// Path to a directory previously downloaded to, whose 'metadata.ndjson' is updated to match the rename
+        #[clap(long)]
+        archive_directory: Option<String>,
+    },
+    /// Shows who the credentials for SLACK WORKSPACE belong to and whether they can list and add emojis
+    Whoami,
+    /// Uploads emojis from SOURCE WORKSPACE that are missing from DEST WORKSPACE
+    Sync {
+        #[clap(flatten)]
+        workspace_pair_opts: WorkspacePairOpts,
+        /// Path to a directory to keep the downloaded emojis in, which can be reused by later syncs as a cache. A
+        /// temporary directory is used (and removed afterwards) if not provided.
+        #[clap(long)]
+        archive_directory: Option<String>,
+        /// Path to write a JSON report of the outcome for each emoji to, in addition to the summary printed at the end
+        #[clap(long)]
+        report_json: Option<String>,
     },
-    /// Uploads emojis to SLACK WORKSPACE from TARGET DIRECTORY
-    Upload,
 }
 
-impl From<&Opts> for SlackClient {
-    fn from(opts: &Opts) -> Self {
-        Self::new(&opts.token, &opts.session_cookie, &opts.workspace)
+impl From<&WorkspaceOpts> for SlackClient {
+    fn from(opts: &WorkspaceOpts) -> Self {
+        match &opts.api_base_url {
+            Some(api_base_url) => {
+                Self::new_with_base_url(&opts.token, &opts.session_cookie, api_base_url)
+            }
+            None => Self::new(&opts.token, &opts.session_cookie, &opts.workspace),
+        }
+        .with_emoji_cdn_url(opts.emoji_cdn_url.clone())
+    }
+}
+
+impl From<&RetryOpts> for RetryPolicy {
+    fn from(opts: &RetryOpts) -> Self {
+        Self::new(
+            opts.max_attempts,
+            Duration::from_secs(opts.retry_budget_secs),
+        )
     }
 }
 
@@ -88,7 +360,117 @@ impl From<&EmojiStreamOpts> for EmojiStreamParameters {
     }
 }
 
+impl From<&ConflictOpts> for ConflictStrategy {
+    fn from(opts: &ConflictOpts) -> Self {
+        match opts.on_conflict {
+            OnConflict::Skip => Self::Skip,
+            OnConflict::Rename => Self::Rename {
+                prefix: opts.rename_prefix.clone(),
+                suffix: opts.rename_suffix.clone(),
+            },
+            OnConflict::Overwrite => Self::Overwrite,
+        }
+    }
+}
+
+fn read_names_file(path: &str) -> Result<HashSet<String>, String> {
+    std::fs::read_to_string(path)
+        .map(|contents| parse_names(&contents))
+        .map_err(|e| format!("could not read '{}': {}", path, e))
+}
+
+impl From<&EmojiFilterOpts> for EmojiFilter {
+    fn from(opts: &EmojiFilterOpts) -> Self {
+        Self {
+            names: if opts.names.is_empty() && opts.names_file.is_none() {
+                None
+            } else {
+                Some(
+                    opts.names
+                        .iter()
+                        .cloned()
+                        .chain(opts.names_file.iter().flatten().cloned())
+                        .collect(),
+                )
+            },
+            name_patterns: opts
+                .name_glob
+                .iter()
+                .cloned()
+                .map(NamePattern::Glob)
+                .chain(opts.name_regex.iter().cloned().map(NamePattern::Regex))
+                .collect(),
+            added_by: opts.added_by.clone(),
+            created_after: opts.created_after,
+            created_before: opts.created_before,
+            aliases: match (opts.aliases_only, opts.no_aliases) {
+                (true, _) => AliasSelection::Only,
+                (_, true) => AliasSelection::Exclude,
+                _ => AliasSelection::Include,
+            },
+        }
+    }
+}
+
+impl SubCommandKind {
+    /// Whether the subcommand takes SLACK WORKSPACE and TARGET DIRECTORY, which come before it
+    fn uses_workspace_and_target_directory(&self) -> (bool, bool) {
+        match self {
+            Self::Download { .. } | Self::Upload { .. } | Self::Diff { .. } => (true, true),
+            Self::Delete { .. } | Self::Rename { .. } | Self::Whoami => (true, false),
+            Self::Verify { .. } | Self::Compact { .. } | Self::Sync { .. } => (false, false),
+        }
+    }
+}
+
 impl Opts {
+    /// Fails if SLACK WORKSPACE or TARGET DIRECTORY is given to a subcommand that has no use for it, rather than
+    /// quietly ignoring it
+    pub fn check_unused_args(&self) -> Result<()> {
+        let (uses_workspace, uses_target_directory) =
+            self.subcommand.uses_workspace_and_target_directory();
+        if !uses_workspace && self.workspace.is_some() {
+            return Err(Error::InvalidInput(String::from(
+                "This subcommand does not take SLACK WORKSPACE; give its arguments after the subcommand",
+            )));
+        }
+        if !uses_target_directory && self.target_directory.is_some() {
+            return Err(Error::InvalidInput(String::from(
+                "This subcommand does not take TARGET DIRECTORY",
+            )));
+        }
+        Ok(())
+    }
+
+    /// The workspace given before the subcommand, along with the credentials for it
+    pub fn workspace_opts(&self) -> Result<WorkspaceOpts> {
+        let workspace = self.workspace.clone().ok_or_else(|| {
+            Error::InvalidInput(String::from(
+                "SLACK WORKSPACE must be given before the subcommand",
+            ))
+        })?;
+        match (&self.token, &self.session_cookie) {
+            (Some(token), Some(session_cookie)) => Ok(WorkspaceOpts {
+                workspace,
+                token: token.clone(),
+                session_cookie: session_cookie.clone(),
+                api_base_url: self.api_base_url.clone(),
+                emoji_cdn_url: self.emoji_cdn_url.clone(),
+            }),
+            _ => Err(Error::InvalidInput(String::from(
+                "A token and session cookie are required; provide them via SLACK_TOKEN and SLACK_SESSION_COOKIE",
+            ))),
+        }
+    }
+
+    pub fn target_directory(&self) -> Result<&str> {
+        self.target_directory.as_deref().ok_or_else(|| {
+            Error::InvalidInput(String::from(
+                "TARGET DIRECTORY must be given before the subcommand",
+            ))
+        })
+    }
+
     fn setup_logging(self) -> Self {
         let verbosity = self.verbosity;
         let env = Env::default()
@@ -108,8 +490,11 @@ impl Opts {
         self
     }
 
-    pub fn create_slack_client(&self) -> Rc<SlackClient> {
-        Rc::new(SlackClient::from(self))
+    pub fn create_slack_client(&self, workspace_opts: &WorkspaceOpts) -> Arc<SlackClient> {
+        Arc::new(
+            SlackClient::from(workspace_opts)
+                .with_retry_policy(RetryPolicy::from(&self.retry_opts)),
+        )
     }
 }
 
@@ -0,0 +1,232 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt;
+
+use colored::Colorize;
+use tokio::fs;
+
+use crate::archive::{EmojiDirectory, EmojiFile};
+use crate::error::Result;
+
+/// A line of metadata that `compact_directory` left out. Line numbers count from 1.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DroppedRecord {
+    pub line: usize,
+    pub name: String,
+    pub filename: String,
+}
+
+impl DroppedRecord {
+    fn new(line: usize, emoji_file: &EmojiFile) -> Self {
+        Self {
+            line,
+            name: emoji_file.emoji.name.clone(),
+            filename: emoji_file.filename.clone(),
+        }
+    }
+}
+
+/// The result of rewriting an `EmojiDirectory` with `compact_directory`
+#[derive(Debug, Default)]
+pub struct CompactionReport {
+    pub records_read: usize,
+    pub records_written: usize,
+    /// Records for an emoji that another record took precedence over
+    pub duplicates: Vec<DroppedRecord>,
+    /// Records whose image no longer exists
+    pub missing_images: Vec<DroppedRecord>,
+}
+
+impl fmt::Display for CompactionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Read {} line(s) of metadata and wrote {}",
+            self.records_read, self.records_written
+        )?;
+        if !self.duplicates.is_empty() {
+            writeln!(f, "Dropped duplicates ({}):", self.duplicates.len())?;
+            for dropped in &self.duplicates {
+                writeln!(
+                    f,
+                    "  {} line {}: {} ({})",
+                    "-".yellow(),
+                    dropped.line,
+                    dropped.name,
+                    dropped.filename
+                )?;
+            }
+        }
+        if !self.missing_images.is_empty() {
+            writeln!(
+                f,
+                "Dropped records whose image is missing ({}):",
+                self.missing_images.len()
+            )?;
+            for dropped in &self.missing_images {
+                writeln!(
+                    f,
+                    "  {} line {}: {} ({})",
+                    "-".yellow(),
+                    dropped.line,
+                    dropped.name,
+                    dropped.filename
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `record`, which comes later in the metadata file, takes precedence over `other` for the same emoji: the
+/// higher revision wins, then the later creation time, then the later line
+fn takes_precedence(record: &EmojiFile, other: &EmojiFile) -> bool {
+    (record.revision, record.emoji.created) >= (other.revision, other.emoji.created)
+}
+
+/// Rewrites the metadata file of `directory` with one record per emoji, sorted by name, so that the same archive
+/// always has the same metadata file. Records whose image is missing are left out first; of the records left for an
+/// emoji, the one that takes precedence is kept. The others are moved to the history file if their image differs
+/// from the one kept, since it is an earlier version of the emoji that would otherwise be orphaned.
+pub async fn compact_directory(directory: &EmojiDirectory) -> Result<CompactionReport> {
+    directory.ensure_is_directory().await?;
+    // Records moved to the history file are appended in the current format
+    directory.upgrade().await?;
+    let emoji_files = directory.read_emoji_files().await?;
+    let mut report = CompactionReport {
+        records_read: emoji_files.len(),
+        ..Default::default()
+    };
+
+    let mut kept: HashMap<String, (usize, EmojiFile)> = HashMap::new();
+    let mut superseded = Vec::new();
+    for (index, emoji_file) in emoji_files.into_iter().enumerate() {
+        let line = index + 1;
+        // Aliases may have no image, e.g. those for standard emojis
+        if emoji_file.emoji.alias_for.is_empty()
+            && fs::metadata(directory.get_emoji_filepath(&emoji_file))
+                .await
+                .is_err()
+        {
+            report
+                .missing_images
+                .push(DroppedRecord::new(line, &emoji_file));
+            continue;
+        }
+
+        match kept.entry(emoji_file.emoji.name.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert((line, emoji_file));
+            }
+            Entry::Occupied(mut entry) => {
+                let (dropped_line, dropped) = if takes_precedence(&emoji_file, &entry.get().1) {
+                    entry.insert((line, emoji_file))
+                } else {
+                    (line, emoji_file)
+                };
+                report
+                    .duplicates
+                    .push(DroppedRecord::new(dropped_line, &dropped));
+                if dropped.filename != entry.get().1.filename {
+                    superseded.push(dropped);
+                }
+            }
+        }
+    }
+    report.duplicates.sort_by_key(|dropped| dropped.line);
+
+    let mut emoji_files: Vec<EmojiFile> = kept
+        .into_values()
+        .map(|(_, emoji_file)| emoji_file)
+        .collect();
+    emoji_files.sort_by(|a, b| a.emoji.name.cmp(&b.emoji.name));
+    report.records_written = emoji_files.len();
+
+    if !superseded.is_empty() {
+        directory.append_history(&superseded).await?;
+    }
+    directory.rewrite_metadata_file(&emoji_files).await?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, filename: &str, alias_for: &str, created: &str, revision: u32) -> String {
+        format!(
+            r#"{{"name":"{}","url":"https://emoji.slack-edge.com/T03C6/{}/{}","added_by":"Jimmy Dean","alias_for":"{}","created":"{}","filename":"{}","revision":{}}}"#,
+            name, name, filename, alias_for, created, filename, revision
+        )
+    }
+
+    #[tokio::test]
+    async fn test_compact_directory() {
+        let temp_directory = tempfile::tempdir().unwrap();
+        let directory = EmojiDirectory::new(temp_directory.path());
+        for filename in [
+            "zuck-old.png",
+            "zuck-new.png",
+            "zuck-newest.png",
+            "blob.png",
+        ] {
+            fs::write(directory.get_inner_filepath(filename), b"image")
+                .await
+                .unwrap();
+        }
+        let lines = [
+            record("zuck", "zuck-new.png", "", "2021-01-01T00:00:00Z", 1),
+            record("zuck", "zuck-old.png", "", "2020-01-01T00:00:00Z", 1),
+            record("parrot", "parrot.png", "", "2020-01-01T00:00:00Z", 1),
+            record("blob", "blob.png", "", "2020-01-01T00:00:00Z", 1),
+            record("blob-alias", "blob.png", "blob", "2020-01-01T00:00:00Z", 1),
+            // Never took precedence, since its image is missing
+            record("zuck", "zuck-gone.png", "", "2022-01-01T00:00:00Z", 2),
+            record("blob", "blob.png", "", "2020-01-01T00:00:00Z", 1),
+        ];
+        fs::write(directory.get_metadata_filepath(), lines.join("\n") + "\n")
+            .await
+            .unwrap();
+
+        let report = compact_directory(&directory).await.unwrap();
+        assert_eq!(report.records_read, 7);
+        assert_eq!(report.records_written, 3);
+        assert_eq!(
+            report.duplicates,
+            [
+                DroppedRecord {
+                    line: 2,
+                    name: String::from("zuck"),
+                    filename: String::from("zuck-old.png")
+                },
+                DroppedRecord {
+                    line: 4,
+                    name: String::from("blob"),
+                    filename: String::from("blob.png")
+                }
+            ]
+        );
+        assert_eq!(report.missing_images.len(), 2);
+
+        let emoji_files = directory.read_emoji_files().await.unwrap();
+        let kept: Vec<(&str, &str)> = emoji_files
+            .iter()
+            .map(|emoji_file| (emoji_file.emoji.name.as_str(), emoji_file.filename.as_str()))
+            .collect();
+        assert_eq!(
+            kept,
+            [
+                ("blob", "blob.png"),
+                ("blob-alias", "blob.png"),
+                ("zuck", "zuck-new.png")
+            ]
+        );
+        // Only the duplicate with an image of its own is kept in the history
+        let history = directory.read_history().await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].filename, "zuck-old.png");
+
+        // Compacting again changes nothing
+        let report = compact_directory(&directory).await.unwrap();
+        assert!(report.duplicates.is_empty() && report.missing_images.is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::archive::EmojiFile;
+use crate::emoji::EmojiCollection;
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EmojiChange {
+    /// The emoji is an alias in at least one of the two places, and it does not alias the same emoji in both; an
+    /// empty `alias_for` means that the emoji is not an alias there
+    AliasTarget {
+        name: String,
+        archived_alias_for: String,
+        remote_alias_for: String,
+    },
+    /// Neither is an alias, but the image hashes in their URLs differ
+    Image {
+        name: String,
+        archived_url: String,
+        remote_url: String,
+    },
+}
+
+impl EmojiChange {
+    fn name(&self) -> &str {
+        match self {
+            Self::AliasTarget { name, .. } | Self::Image { name, .. } => name,
+        }
+    }
+}
+
+/// Differences between the emojis in an archive (an `EmojiDirectory`) and those in a workspace
+#[derive(Debug, Default, Serialize)]
+pub struct EmojiDiff {
+    pub only_in_archive: Vec<String>,
+    /// Only in the archive, where they are marked as deleted upstream, so `upload` leaves them out by default
+    pub deleted_upstream: Vec<String>,
+    pub only_in_remote: Vec<String>,
+    pub changed: Vec<EmojiChange>,
+}
+
+impl EmojiDiff {
+    pub fn new<I: IntoIterator<Item = EmojiFile>>(
+        archived_emoji_files: I,
+        remote_emoji_collection: &EmojiCollection,
+    ) -> Self {
+        // Later lines of metadata.ndjson take precedence over earlier ones with the same name
+        let archived: HashMap<String, EmojiFile> = archived_emoji_files
+            .into_iter()
+            .map(|emoji_file| (emoji_file.emoji.name.clone(), emoji_file))
+            .collect();
+
+        let mut diff = Self::default();
+        for (name, emoji_file) in &archived {
+            let remote_emoji = match remote_emoji_collection.get(name) {
+                Some(remote_emoji) => remote_emoji,
+                None if emoji_file.deleted_upstream_at.is_some() => {
+                    diff.deleted_upstream.push(name.clone());
+                    continue;
+                }
+                None => {
+                    diff.only_in_archive.push(name.clone());
+                    continue;
+                }
+            };
+
+            if emoji_file.emoji.alias_for != remote_emoji.alias_for {
+                diff.changed.push(EmojiChange::AliasTarget {
+                    name: name.clone(),
+                    archived_alias_for: emoji_file.emoji.alias_for.clone(),
+                    remote_alias_for: remote_emoji.alias_for.clone(),
+                });
+            } else if emoji_file.emoji.alias_for.is_empty()
+                && emoji_file.filename != EmojiFile::generate_filename_from_url(&remote_emoji.url)
+            {
+                diff.changed.push(EmojiChange::Image {
+                    name: name.clone(),
+                    archived_url: emoji_file.emoji.url.clone(),
+                    remote_url: remote_emoji.url.clone(),
+                });
+            }
+        }
+        diff.only_in_remote = remote_emoji_collection
+            .iter()
+            .filter(|emoji| !archived.contains_key(&emoji.name))
+            .map(|emoji| emoji.name.clone())
+            .collect();
+
+        diff.only_in_archive.sort();
+        diff.deleted_upstream.sort();
+        diff.only_in_remote.sort();
+        diff.changed.sort_by(|a, b| a.name().cmp(b.name()));
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.only_in_archive.is_empty()
+            && self.deleted_upstream.is_empty()
+            && self.only_in_remote.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for EmojiDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "Archive and workspace have the same emojis");
+        }
+
+        if !self.only_in_archive.is_empty() {
+            writeln!(f, "Only in archive ({}):", self.only_in_archive.len())?;
+            for name in &self.only_in_archive {
+                writeln!(f, "  {} {}", "+".green(), name)?;
+            }
+        }
+        if !self.deleted_upstream.is_empty() {
+            writeln!(
+                f,
+                "Only in archive, marked as deleted upstream ({}):",
+                self.deleted_upstream.len()
+            )?;
+            for name in &self.deleted_upstream {
+                writeln!(f, "  {} {}", "x".bright_black(), name)?;
+            }
+        }
+        if !self.only_in_remote.is_empty() {
+            writeln!(f, "Only in workspace ({}):", self.only_in_remote.len())?;
+            for name in &self.only_in_remote {
+                writeln!(f, "  {} {}", "-".red(), name)?;
+            }
+        }
+        if !self.changed.is_empty() {
+            writeln!(f, "Different ({}):", self.changed.len())?;
+            for change in &self.changed {
+                match change {
+                    EmojiChange::AliasTarget {
+                        name,
+                        archived_alias_for,
+                        remote_alias_for,
+                    } => writeln!(
+                        f,
+                        "  {} {}: alias for {} in archive, {} in workspace",
+                        "~".yellow(),
+                        name,
+                        describe_alias_for(archived_alias_for),
+                        describe_alias_for(remote_alias_for)
+                    )?,
+                    EmojiChange::Image {
+                        name,
+                        archived_url,
+                        remote_url,
+                    } => writeln!(
+                        f,
+                        "  {} {}: image differs ({} in archive, {} in workspace)",
+                        "~".yellow(),
+                        name,
+                        archived_url,
+                        remote_url
+                    )?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn describe_alias_for(alias_for: &str) -> String {
+    if alias_for.is_empty() {
+        String::from("nothing")
+    } else {
+        format!("'{}'", alias_for)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emoji::Emoji;
+    use chrono::prelude::*;
+
+    fn emoji(name: &str, alias_for: &str, hash: &str) -> Emoji {
+        Emoji {
+            name: name.to_string(),
+            url: format!("https://emoji.slack-edge.com/T12345/{}/{}.png", name, hash),
+            added_by: String::from("Jimmy Dean"),
+            alias_for: alias_for.to_string(),
+            created: Utc.timestamp_opt(1595443479, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_emoji_diff() {
+        let archived = vec![
+            EmojiFile::from(emoji("same", "", "aaa")),
+            EmojiFile::from(emoji("archive-only", "", "bbb")),
+            EmojiFile::from(emoji("new-image", "", "ccc")),
+            EmojiFile::from(emoji("retargeted", "same", "aaa")),
+            EmojiFile {
+                deleted_upstream_at: Some(Utc.timestamp_opt(1595443479, 0).unwrap()),
+                ..EmojiFile::from(emoji("deleted", "", "ggg"))
+            },
+        ];
+        let mut remote = EmojiCollection::new();
+        remote.insert(emoji("same", "", "aaa"));
+        remote.insert(emoji("remote-only", "", "ddd"));
+        remote.insert(emoji("new-image", "", "eee"));
+        remote.insert(emoji("retargeted", "", "fff"));
+
+        let diff = EmojiDiff::new(archived, &remote);
+        assert_eq!(diff.only_in_archive, vec!["archive-only"]);
+        assert_eq!(diff.deleted_upstream, vec!["deleted"]);
+        assert_eq!(diff.only_in_remote, vec!["remote-only"]);
+        assert_eq!(
+            diff.changed,
+            vec![
+                EmojiChange::Image {
+                    name: String::from("new-image"),
+                    archived_url: String::from(
+                        "https://emoji.slack-edge.com/T12345/new-image/ccc.png"
+                    ),
+                    remote_url: String::from(
+                        "https://emoji.slack-edge.com/T12345/new-image/eee.png"
+                    ),
+                },
+                EmojiChange::AliasTarget {
+                    name: String::from("retargeted"),
+                    archived_alias_for: String::from("same"),
+                    remote_alias_for: String::new(),
+                },
+            ]
+        );
+    }
+}
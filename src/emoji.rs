@@ -1,8 +1,7 @@
 use std::collections::hash_map::HashMap;
-use std::error::Error;
 use std::fmt;
-use std::rc::Rc;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use async_stream::try_stream;
 use chrono::prelude::*;
@@ -14,9 +13,10 @@ use serde::{
     Deserialize, Deserializer, Serialize,
 };
 
+use crate::error::Result;
 use crate::slack::SlackClient;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Emoji {
     pub name: String,
     pub url: String,
@@ -96,6 +96,11 @@ impl EmojiStreamParameters {
             limit_num_pages,
         }
     }
+
+    /// Whether a stream with these parameters lists every emoji in the workspace, rather than only some pages
+    pub fn lists_every_page(&self) -> bool {
+        self.starting_page_number == DEFAULT_STARTING_PAGE && self.limit_num_pages.is_none()
+    }
 }
 
 #[derive(Debug)]
@@ -105,7 +110,7 @@ pub enum EmojiExistenceKind {
     DoesNotExist,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct EmojiCollection(HashMap<String, Emoji>);
 
 impl EmojiCollection {
@@ -117,6 +122,14 @@ impl EmojiCollection {
         self.0.insert(emoji.name.clone(), emoji)
     }
 
+    pub fn get(&self, name: &str) -> Option<&Emoji> {
+        self.0.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Emoji> {
+        self.0.values()
+    }
+
     pub fn get_existence_status<T: AsRef<str>>(&self, name: T) -> EmojiExistenceKind {
         match self.0.get(name.as_ref()) {
             Some(emoji) => {
@@ -130,24 +143,26 @@ impl EmojiCollection {
         }
     }
 
-    pub async fn from_new_emoji_stream(client: Rc<SlackClient>) -> Self {
+    /// Fetches every emoji in the workspace of `client`. Fails rather than returning a partial collection, since
+    /// callers decide what to upload or delete based on what is missing from it.
+    pub async fn from_new_emoji_stream(client: Arc<SlackClient>) -> Result<Self> {
         let mut collection = Self::new();
 
         let stream = new_emoji_stream(client.clone(), None);
         pin_mut!(stream);
 
-        while let Some(Ok(emoji)) = stream.next().await {
-            collection.insert(emoji);
+        while let Some(emoji) = stream.next().await {
+            collection.insert(emoji?);
         }
 
-        collection
+        Ok(collection)
     }
 }
 
 pub fn new_emoji_stream(
-    slack_client: Rc<SlackClient>,
+    slack_client: Arc<SlackClient>,
     stream_parameters: Option<EmojiStreamParameters>,
-) -> impl Stream<Item = Result<Emoji, Box<dyn Error>>> {
+) -> impl Stream<Item = Result<Emoji>> + Send + Sync {
     try_stream! {
         let parameters = stream_parameters.unwrap_or_default();
         let mut current_page_number = parameters.starting_page_number;
@@ -0,0 +1,163 @@
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Slack rejected the token or session cookie
+    #[error("Slack rejected the credentials ({code}); check the token and session cookie")]
+    Auth { code: String },
+    /// Slack kept rate limiting a request until the retry policy gave up on it
+    #[error("Slack kept rate limiting {request}; try again later or allow more retries")]
+    RateLimited { request: String },
+    /// A request kept failing for a reason other than rate limiting until the retry policy gave up on it
+    #[error("Could not complete {request} within {attempts} attempt(s); last failure: {reason}")]
+    RetriesExhausted {
+        request: String,
+        attempts: u32,
+        reason: String,
+    },
+    /// The credentials are valid, but not allowed to do something that the subcommand needs to
+    #[error(
+        "{user} in {team} is not allowed to {action}; an administrator may need to grant access"
+    )]
+    PermissionDenied {
+        user: String,
+        team: String,
+        action: String,
+    },
+    #[error("Emoji name {name} is already taken")]
+    NameTaken { name: String },
+    #[error("Slack rejected the image for emoji {name} ({code})")]
+    BadImage { name: String, code: String },
+    /// A downloaded emoji image was cut short or is not an image at all
+    #[error("Download of {url} did not produce a valid image: {reason}")]
+    InvalidImage { url: String, reason: String },
+    /// Any other error code returned by the Slack API
+    #[error("Slack responded to {request} with error: {code}")]
+    SlackApi { request: String, code: String },
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Line {line} of {} is corrupt: {source}", path.display())]
+    CorruptMetadata {
+        path: PathBuf,
+        line: usize,
+        source: serde_json::Error,
+    },
+    /// An archive was written by a newer version of the tool, in a format that this version cannot read
+    #[error("{} has format version {found}, but only versions up to {supported} are supported; a newer version of this tool is needed", path.display())]
+    UnsupportedFormatVersion {
+        path: PathBuf,
+        found: u32,
+        supported: u32,
+    },
+    /// `verify` found problems with an archive
+    #[error("Found {count} problem(s) with the archive")]
+    ArchiveProblems { count: usize },
+    #[error("Could not serialize JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("\"{}\" is not a directory", .0.display())]
+    NotADirectory(PathBuf),
+    /// A run finished, but not every emoji made it through
+    #[error("{failed} emoji(s) failed and {errors} other error(s) occurred; see the summary for details")]
+    Incomplete { failed: usize, errors: usize },
+    /// The arguments given cannot be acted upon, e.g. renaming an emoji that does not exist
+    #[error("{0}")]
+    InvalidInput(String),
+}
+
+impl Error {
+    /// Classifies an `error` code returned by the Slack API in response to `request`. `name` is the emoji that the
+    /// request was about, if any.
+    pub fn from_slack_error<S: Into<String>>(request: S, name: Option<&str>, code: String) -> Self {
+        match (code.as_str(), name) {
+            (
+                "invalid_auth" | "not_authed" | "account_inactive" | "token_revoked"
+                | "token_expired",
+                _,
+            ) => Self::Auth { code },
+            ("ratelimited", _) => Self::RateLimited {
+                request: request.into(),
+            },
+            ("error_name_taken" | "error_name_taken_i18n", Some(name)) => Self::NameTaken {
+                name: name.to_string(),
+            },
+            (
+                "error_bad_format"
+                | "error_bad_upload"
+                | "error_too_big"
+                | "no_image_uploaded"
+                | "resized_but_still_too_large"
+                | "too_many_frames",
+                Some(name),
+            ) => Self::BadImage {
+                name: name.to_string(),
+                code,
+            },
+            _ => Self::SlackApi {
+                request: request.into(),
+                code,
+            },
+        }
+    }
+
+    /// The code that the CLI exits with when it fails with this error. 2 is shared with usage errors reported by clap,
+    /// and 1 covers every error that scripts are unlikely to react to specifically.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::RetriesExhausted { .. }
+            | Self::SlackApi { .. }
+            | Self::Http(_)
+            | Self::Json(_) => 1,
+            Self::InvalidInput(_) | Self::NotADirectory(_) => 2,
+            Self::Auth { .. } => 3,
+            Self::RateLimited { .. } => 4,
+            Self::NameTaken { .. } => 5,
+            Self::BadImage { .. } | Self::InvalidImage { .. } => 6,
+            Self::Io(_) => 7,
+            Self::CorruptMetadata { .. }
+            | Self::UnsupportedFormatVersion { .. }
+            | Self::ArchiveProblems { .. } => 8,
+            Self::PermissionDenied { .. } => 9,
+            Self::Incomplete { .. } => 10,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_slack_error() {
+        let from_code =
+            |code: &str| Error::from_slack_error("emoji.add", Some("zuck"), code.into());
+
+        assert!(matches!(from_code("invalid_auth"), Error::Auth { .. }));
+        assert!(matches!(from_code("not_authed"), Error::Auth { .. }));
+        assert!(matches!(
+            from_code("ratelimited"),
+            Error::RateLimited { .. }
+        ));
+        assert!(matches!(
+            from_code("error_name_taken_i18n"),
+            Error::NameTaken { name } if name == "zuck"
+        ));
+        assert!(matches!(from_code("error_too_big"), Error::BadImage { .. }));
+        assert!(matches!(
+            from_code("error_invalid_alias"),
+            Error::SlackApi { code, .. } if code == "error_invalid_alias"
+        ));
+        // Without an emoji to blame, name conflicts cannot be told apart from any other error
+        assert!(matches!(
+            Error::from_slack_error("emoji.adminList", None, "error_name_taken".into()),
+            Error::SlackApi { .. }
+        ));
+        assert_eq!(from_code("not_authed").exit_code(), 3);
+    }
+}
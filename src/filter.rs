@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+
+use chrono::prelude::*;
+use glob::Pattern;
+use regex::Regex;
+
+use crate::emoji::Emoji;
+
+#[derive(Debug, Clone)]
+pub enum NamePattern {
+    Glob(Pattern),
+    Regex(Regex),
+}
+
+impl NamePattern {
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Self::Glob(pattern) => pattern.matches(name),
+            Self::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// Whether aliases are selected along with other emojis
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AliasSelection {
+    #[default]
+    Include,
+    Only,
+    Exclude,
+}
+
+/// Selects emojis by a combination of criteria; an emoji must meet every criterion that is set to be selected
+#[derive(Debug, Default)]
+pub struct EmojiFilter {
+    pub names: Option<HashSet<String>>,
+    pub name_patterns: Vec<NamePattern>,
+    pub added_by: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub aliases: AliasSelection,
+}
+
+impl EmojiFilter {
+    /// Whether no criteria are set, in which case every emoji is selected
+    pub fn is_empty(&self) -> bool {
+        self.names.is_none()
+            && self.name_patterns.is_empty()
+            && self.added_by.is_none()
+            && self.created_after.is_none()
+            && self.created_before.is_none()
+            && self.aliases == AliasSelection::Include
+    }
+
+    pub fn matches(&self, emoji: &Emoji) -> bool {
+        if let Some(names) = &self.names {
+            if !names.contains(&emoji.name) {
+                return false;
+            }
+        }
+        if !self
+            .name_patterns
+            .iter()
+            .all(|pattern| pattern.matches(&emoji.name))
+        {
+            return false;
+        }
+        if let Some(added_by) = &self.added_by {
+            if &emoji.added_by != added_by {
+                return false;
+            }
+        }
+        if let Some(created_after) = self.created_after {
+            if emoji.created <= created_after {
+                return false;
+            }
+        }
+        if let Some(created_before) = self.created_before {
+            if emoji.created >= created_before {
+                return false;
+            }
+        }
+        match self.aliases {
+            AliasSelection::Include => true,
+            AliasSelection::Only => !emoji.alias_for.is_empty(),
+            AliasSelection::Exclude => emoji.alias_for.is_empty(),
+        }
+    }
+}
+
+/// Reads a list of emoji names, one per line. Surrounding colons (as in ":partyparrot:") are stripped, and blank lines
+/// and lines starting with '#' are skipped.
+pub fn parse_names(contents: &str) -> HashSet<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.trim_matches(':').to_string())
+        .collect()
+}
+
+/// Parses either an RFC 3339 date and time (e.g. "2022-01-31T12:00:00Z") or a date (e.g. "2022-01-31"), which is
+/// taken to mean midnight UTC
+pub fn parse_datetime(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Ok(datetime.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()))
+        .map_err(|_| {
+            format!(
+                "'{}' is neither a date nor an RFC 3339 date and time",
+                value
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emoji(name: &str, added_by: &str, created: &str) -> Emoji {
+        Emoji {
+            name: name.to_string(),
+            url: format!("https://emoji.slack-edge.com/T12345/{}/abc.png", name),
+            added_by: added_by.to_string(),
+            alias_for: String::new(),
+            created: parse_datetime(created).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_emoji_filter() {
+        let parrot = emoji("partyparrot", "Jimmy Dean", "2020-07-22");
+        let blob = emoji("blob-wave", "SPOONBEARD", "2022-01-31T12:00:00Z");
+
+        assert!(EmojiFilter::default().matches(&parrot));
+
+        let filter = EmojiFilter {
+            name_patterns: vec![NamePattern::Glob(Pattern::new("party*").unwrap())],
+            ..Default::default()
+        };
+        assert!(filter.matches(&parrot) && !filter.matches(&blob));
+
+        let filter = EmojiFilter {
+            name_patterns: vec![NamePattern::Regex(Regex::new("^blob-").unwrap())],
+            added_by: Some(String::from("SPOONBEARD")),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&parrot) && filter.matches(&blob));
+
+        let filter = EmojiFilter {
+            names: Some(HashSet::from([String::from("blob-wave")])),
+            created_before: Some(parse_datetime("2022-01-31").unwrap()),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&parrot) && !filter.matches(&blob));
+
+        let filter = EmojiFilter {
+            created_after: Some(parse_datetime("2021-01-01").unwrap()),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&parrot) && filter.matches(&blob));
+
+        let parrot_alias = Emoji {
+            alias_for: String::from("partyparrot"),
+            ..emoji("parrot", "Jimmy Dean", "2020-07-23")
+        };
+        let filter = EmojiFilter {
+            aliases: AliasSelection::Only,
+            ..Default::default()
+        };
+        assert!(filter.matches(&parrot_alias) && !filter.matches(&parrot));
+        let filter = EmojiFilter {
+            aliases: AliasSelection::Exclude,
+            ..Default::default()
+        };
+        assert!(!filter.matches(&parrot_alias) && filter.matches(&parrot));
+    }
+
+    #[test]
+    fn test_parse_names() {
+        assert_eq!(
+            parse_names("# Curated\npartyparrot\n\n  :blob-wave:  \n"),
+            HashSet::from([String::from("partyparrot"), String::from("blob-wave")])
+        );
+    }
+}
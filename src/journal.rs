@@ -0,0 +1,175 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::prelude::*;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::archive::EmojiDirectory;
+use crate::error::Result;
+use crate::report::EmojiOutcome;
+
+pub(crate) const UPLOAD_JOURNAL_FILENAME: &str = "upload-journal.ndjson";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub name: String,
+    #[serde(flatten)]
+    pub outcome: EmojiOutcome,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// Records the outcome of each emoji as an upload goes, so that an interrupted upload can be resumed. The journal is
+/// kept in the `EmojiDirectory` being uploaded from, one JSON object per line like the metadata file.
+pub struct UploadJournal {
+    handle: File,
+    entries: Vec<JournalEntry>,
+}
+
+impl UploadJournal {
+    /// Starts a new journal, discarding any journal left by an earlier upload
+    pub async fn start(directory: &EmojiDirectory) -> Result<Self> {
+        let handle = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(directory.get_inner_filepath(UPLOAD_JOURNAL_FILENAME))
+            .await?;
+        Ok(Self {
+            handle,
+            entries: Vec::new(),
+        })
+    }
+
+    /// Reads the journal left by an earlier upload, if any, and appends to it from then on. Lines that cannot be
+    /// parsed, such as one cut short when the earlier upload was interrupted, are skipped.
+    pub async fn resume(directory: &EmojiDirectory) -> Result<Self> {
+        let filepath = directory.get_inner_filepath(UPLOAD_JOURNAL_FILENAME);
+        let handle = OpenOptions::new()
+            .append(true)
+            .read(true)
+            .create(true)
+            .open(&filepath)
+            .await?;
+
+        let mut lines = BufReader::new(handle.try_clone().await?).lines();
+        let mut entries = Vec::new();
+        let mut line_number = 0;
+        while let Some(line) = lines.next_line().await? {
+            line_number += 1;
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!(
+                    "Skipping line {} of {}: {}",
+                    line_number,
+                    filepath.display(),
+                    e
+                ),
+            }
+        }
+
+        Ok(Self { handle, entries })
+    }
+
+    /// Reads the journal left by an earlier upload without writing to it, as a dry run does; `None` if there is none
+    pub async fn read(directory: &EmojiDirectory) -> Result<Option<Self>> {
+        if directory
+            .get_inner_filepath(UPLOAD_JOURNAL_FILENAME)
+            .exists()
+        {
+            Ok(Some(Self::resume(directory).await?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn record(&mut self, name: &str, outcome: &EmojiOutcome) -> Result<()> {
+        let entry = JournalEntry {
+            name: name.to_string(),
+            outcome: outcome.clone(),
+            recorded_at: Utc::now(),
+        };
+        let mut entry_bytes = serde_json::to_vec(&entry)?;
+        entry_bytes.extend_from_slice(b"\n");
+        self.handle.write_all(&entry_bytes).await?;
+        self.handle.flush().await?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Names of the emojis whose latest entry is anything but a failure, which a resumed upload need not attempt again
+    pub fn completed_names(&self) -> HashSet<String> {
+        let latest: HashMap<&str, &EmojiOutcome> = self
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), &entry.outcome))
+            .collect();
+        latest
+            .into_iter()
+            .filter(|(_, outcome)| !matches!(outcome, EmojiOutcome::Failed(_)))
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    /// When the journal was last written to, if ever
+    pub fn last_recorded_at(&self) -> Option<DateTime<Utc>> {
+        self.entries.iter().map(|entry| entry.recorded_at).max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resume_upload_journal() {
+        let temp_directory = tempfile::tempdir().unwrap();
+        let directory = EmojiDirectory::new(temp_directory.path());
+
+        let mut journal = UploadJournal::start(&directory).await.unwrap();
+        assert!(journal.last_recorded_at().is_none());
+        journal
+            .record(
+                "parrot",
+                &EmojiOutcome::Failed(String::from("error_too_big")),
+            )
+            .await
+            .unwrap();
+        journal
+            .record("blob", &EmojiOutcome::Uploaded)
+            .await
+            .unwrap();
+        journal
+            .record("seal", &EmojiOutcome::SkippedStandardShortcode)
+            .await
+            .unwrap();
+        journal
+            .record(
+                "blob-wave",
+                &EmojiOutcome::Failed(String::from("no_image_uploaded")),
+            )
+            .await
+            .unwrap();
+        journal
+            .record("blob-wave", &EmojiOutcome::Uploaded)
+            .await
+            .unwrap();
+        drop(journal);
+
+        let journal = UploadJournal::resume(&directory).await.unwrap();
+        assert!(journal.last_recorded_at().is_some());
+        assert_eq!(
+            journal.completed_names(),
+            HashSet::from([
+                String::from("blob"),
+                String::from("seal"),
+                String::from("blob-wave")
+            ])
+        );
+
+        UploadJournal::start(&directory).await.unwrap();
+        let journal = UploadJournal::resume(&directory).await.unwrap();
+        assert!(journal.completed_names().is_empty());
+    }
+}
@@ -0,0 +1,29 @@
+//! Library for downloading emojis from or uploading emojis to a Slack workspace, which the `slack_emoji` CLI is built
+//! on. `SlackClient` talks to Slack's emoji endpoints, `new_emoji_stream` pages through a workspace's custom emojis,
+//! and `EmojiDirectory` reads and writes an archive of emojis on disk. The functions in `actions` implement each CLI
+//! subcommand in terms of those.
+
+pub mod actions;
+pub mod alias;
+pub mod archive;
+pub mod compact;
+pub mod diff;
+pub mod emoji;
+pub mod error;
+pub mod filter;
+pub mod journal;
+pub mod report;
+pub mod retry;
+pub mod slack;
+pub mod verify;
+
+pub use actions::{
+    compact, delete, diff, download, preflight, rename, sync, upload, verify, whoami,
+    ConflictStrategy, DownloadOptions, RequiredAccess, UploadOptions, DEFAULT_DOWNLOAD_CONCURRENCY,
+};
+pub use archive::{EmojiDirectory, EmojiFile};
+pub use emoji::{new_emoji_stream, Emoji, EmojiCollection, EmojiStreamParameters};
+pub use error::{Error, Result};
+pub use report::RunReport;
+pub use retry::RetryPolicy;
+pub use slack::SlackClient;
@@ -1,28 +1,171 @@
-use futures::future::Either::{Left, Right};
-use std::error::Error;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::emoji::EmojiStreamParameters;
-use actions::{download, upload};
-use cli::{get_opts, SubCommandKind};
+use cli::{get_opts, Opts, SubCommandKind, WorkspaceOpts};
+use slack_emoji::error::Result;
+use slack_emoji::filter::EmojiFilter;
+use slack_emoji::{
+    compact, delete, diff, download, preflight, rename, sync, upload, verify, whoami,
+    ConflictStrategy, DownloadOptions, EmojiStreamParameters, RequiredAccess, RunReport,
+    SlackClient, UploadOptions,
+};
 
-mod actions;
-mod archive;
 mod cli;
-mod emoji;
-mod slack;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() {
     let opts = get_opts();
-    let slack_client = opts.create_slack_client();
-    let target_directory = &opts.target_directory;
-    match opts.subcommand {
-        SubCommandKind::Download { emoji_stream_opts } => Left(download(
-            slack_client,
+    if let Err(e) = run(&opts).await {
+        eprintln!("Error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+/// Creates a client for `workspace_opts`, failing fast if its credentials do not allow `required_access`
+async fn connect(
+    opts: &Opts,
+    workspace_opts: &WorkspaceOpts,
+    required_access: RequiredAccess,
+) -> Result<Arc<SlackClient>> {
+    let client = opts.create_slack_client(workspace_opts);
+    preflight(&client, required_access).await?;
+    Ok(client)
+}
+
+/// Prints the summary of a download, upload, sync or delete and writes it to `report_json`, if set, before failing if
+/// any emoji did not make it through
+async fn finish_run(report: RunReport, report_json: Option<&String>) -> Result<()> {
+    print!("{}", report);
+    if let Some(report_json) = report_json {
+        report.write_json(report_json).await?;
+    }
+    report.into_result().map(|_| ())
+}
+
+async fn run(opts: &Opts) -> Result<()> {
+    opts.check_unused_args()?;
+    match &opts.subcommand {
+        SubCommandKind::Download {
+            emoji_stream_opts,
+            emoji_filter_opts,
+            concurrency,
+            mark_deleted,
+            dry_run,
+            report_json,
+        } => {
+            let report = download(
+                connect(opts, &opts.workspace_opts()?, RequiredAccess::List).await?,
+                opts.target_directory()?,
+                EmojiStreamParameters::from(emoji_stream_opts),
+                &EmojiFilter::from(emoji_filter_opts),
+                &DownloadOptions {
+                    concurrency: *concurrency,
+                    mark_deleted: *mark_deleted,
+                    dry_run: *dry_run,
+                },
+            )
+            .await?;
+            finish_run(report, report_json.as_ref()).await
+        }
+        SubCommandKind::Upload {
+            emoji_filter_opts,
+            report_json,
+            resume,
+            refetch_after_mins,
+            include_deleted,
+            conflict_opts,
+            dry_run,
+        } => {
+            let required_access = if *dry_run {
+                RequiredAccess::List
+            } else {
+                RequiredAccess::ListAndAdd
+            };
+            let report = upload(
+                connect(opts, &opts.workspace_opts()?, required_access).await?,
+                opts.target_directory()?,
+                &EmojiFilter::from(emoji_filter_opts),
+                &UploadOptions {
+                    resume: *resume,
+                    refetch_after: Duration::from_secs(refetch_after_mins * 60),
+                    include_deleted: *include_deleted,
+                    on_conflict: ConflictStrategy::from(conflict_opts),
+                    dry_run: *dry_run,
+                },
+            )
+            .await?;
+            finish_run(report, report_json.as_ref()).await
+        }
+        SubCommandKind::Diff { json } => {
+            diff(
+                connect(opts, &opts.workspace_opts()?, RequiredAccess::List).await?,
+                opts.target_directory()?,
+                *json,
+            )
+            .await
+        }
+        SubCommandKind::Verify {
             target_directory,
-            EmojiStreamParameters::from(&emoji_stream_opts),
-        )),
-        SubCommandKind::Upload => Right(upload(slack_client, target_directory)),
+            json,
+        } => verify(target_directory, *json).await,
+        SubCommandKind::Compact { target_directory } => compact(target_directory).await,
+        SubCommandKind::Delete {
+            emoji_filter_opts,
+            yes,
+            dry_run,
+        } => {
+            let required_access = if *dry_run {
+                RequiredAccess::List
+            } else {
+                RequiredAccess::ListAndAdd
+            };
+            let report = delete(
+                connect(opts, &opts.workspace_opts()?, required_access).await?,
+                EmojiFilter::from(emoji_filter_opts),
+                *yes,
+                *dry_run,
+            )
+            .await?;
+            finish_run(report, None).await
+        }
+        SubCommandKind::Rename {
+            old_name,
+            new_name,
+            archive_directory,
+        } => {
+            rename(
+                connect(opts, &opts.workspace_opts()?, RequiredAccess::ListAndAdd).await?,
+                old_name,
+                new_name,
+                archive_directory.as_ref(),
+            )
+            .await
+        }
+        SubCommandKind::Whoami => whoami(opts.create_slack_client(&opts.workspace_opts()?)).await,
+        SubCommandKind::Sync {
+            workspace_pair_opts,
+            archive_directory,
+            report_json,
+        } => {
+            let source_client =
+                connect(opts, &workspace_pair_opts.source(), RequiredAccess::List).await?;
+            let dest_client = connect(
+                opts,
+                &workspace_pair_opts.dest(),
+                RequiredAccess::ListAndAdd,
+            )
+            .await?;
+            let report = match archive_directory {
+                Some(archive_directory) => {
+                    sync(source_client, dest_client, archive_directory).await?
+                }
+                None => {
+                    let temp_directory = tempfile::tempdir()?;
+                    sync(source_client, dest_client, temp_directory.path()).await?
+                }
+            };
+            finish_run(report, report_json.as_ref()).await
+        }
     }
-    .await
 }
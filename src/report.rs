@@ -0,0 +1,243 @@
+use std::fmt;
+use std::mem;
+use std::path::Path;
+
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// What happened to a single emoji during a download, upload, sync or delete
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "outcome", content = "reason", rename_all = "snake_case")]
+pub enum EmojiOutcome {
+    Downloaded,
+    /// Downloaded again since it changed upstream, e.g. it was deleted and re-created with a new image
+    Updated,
+    Uploaded,
+    /// Uploaded under the name given, since its own was taken
+    Renamed(String),
+    /// Uploaded in place of a different emoji of the same name, which was removed
+    Overwritten,
+    /// In the archive but no longer in the workspace, and now marked as deleted upstream
+    MarkedDeleted,
+    /// Marked as deleted upstream but listed again unchanged, and no longer marked
+    Reappeared,
+    /// Removed from the workspace
+    Deleted,
+    /// Already in the archive when downloading, or already in the workspace when uploading
+    SkippedExisting,
+    /// Not uploaded since its name is taken by a standard Unicode emoji
+    SkippedStandardShortcode,
+    Failed(String),
+}
+
+impl EmojiOutcome {
+    /// Whether the outcome changes anything, as opposed to skipping the emoji or failing
+    fn is_change(&self) -> bool {
+        matches!(
+            self,
+            Self::Downloaded
+                | Self::Updated
+                | Self::Uploaded
+                | Self::Renamed(_)
+                | Self::Overwritten
+                | Self::MarkedDeleted
+                | Self::Reappeared
+                | Self::Deleted
+        )
+    }
+
+    fn label(&self, dry_run: bool) -> &'static str {
+        match self {
+            Self::Downloaded if dry_run => "Would download",
+            Self::Updated if dry_run => "Would update (changed upstream)",
+            Self::Uploaded if dry_run => "Would upload",
+            Self::Renamed(_) if dry_run => "Would upload under a new name",
+            Self::Overwritten if dry_run => "Would overwrite",
+            Self::MarkedDeleted if dry_run => "Would mark deleted upstream",
+            Self::Reappeared if dry_run => "Would unmark deleted upstream (listed again)",
+            Self::Deleted if dry_run => "Would delete",
+            Self::Downloaded => "Downloaded",
+            Self::Updated => "Updated (changed upstream)",
+            Self::Uploaded => "Uploaded",
+            Self::Renamed(_) => "Uploaded under a new name",
+            Self::Overwritten => "Overwritten",
+            Self::MarkedDeleted => "Marked deleted upstream",
+            Self::Reappeared => "Unmarked deleted upstream (listed again)",
+            Self::Deleted => "Deleted",
+            Self::SkippedExisting => "Skipped (already exists)",
+            Self::SkippedStandardShortcode => "Skipped (standard short code)",
+            Self::Failed(_) => "Failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct EmojiResult {
+    pub name: String,
+    #[serde(flatten)]
+    pub outcome: EmojiOutcome,
+}
+
+/// The outcome for each emoji that a run came across, along with any errors that were not specific to one emoji
+/// (e.g. a page of the emoji list that could not be fetched). In a dry run, the outcomes are what would have happened.
+#[derive(Debug, Default, Serialize)]
+pub struct RunReport {
+    pub dry_run: bool,
+    pub emojis: Vec<EmojiResult>,
+    pub errors: Vec<String>,
+}
+
+impl RunReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A report of the changes that a run would make, for a run that makes none
+    pub fn new_dry_run() -> Self {
+        Self {
+            dry_run: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn record<S: Into<String>>(&mut self, name: S, outcome: EmojiOutcome) {
+        self.emojis.push(EmojiResult {
+            name: name.into(),
+            outcome,
+        });
+    }
+
+    pub fn record_error<E: fmt::Display>(&mut self, error: E) {
+        self.errors.push(error.to_string());
+    }
+
+    /// Counts the emojis with the same kind of outcome as `outcome`, disregarding the reason for failures
+    pub fn count(&self, outcome: &EmojiOutcome) -> usize {
+        self.emojis
+            .iter()
+            .filter(|result| mem::discriminant(&result.outcome) == mem::discriminant(outcome))
+            .count()
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.emojis
+            .iter()
+            .filter_map(|result| match &result.outcome {
+                EmojiOutcome::Failed(reason) => Some((result.name.as_str(), reason.as_str())),
+                _ => None,
+            })
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.failures().next().is_some() || !self.errors.is_empty()
+    }
+
+    /// Fails if any emoji failed or any other error occurred, so that the CLI exits with a non-zero code
+    pub fn into_result(self) -> Result<Self> {
+        if self.has_failures() {
+            Err(Error::Incomplete {
+                failed: self.failures().count(),
+                errors: self.errors.len(),
+            })
+        } else {
+            Ok(self)
+        }
+    }
+
+    pub async fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut json = serde_json::to_vec_pretty(self)?;
+        json.push(b'\n');
+        tokio::fs::write(path, json).await?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = [
+            EmojiOutcome::Downloaded,
+            EmojiOutcome::Updated,
+            EmojiOutcome::Uploaded,
+            EmojiOutcome::Renamed(String::new()),
+            EmojiOutcome::Overwritten,
+            EmojiOutcome::MarkedDeleted,
+            EmojiOutcome::Reappeared,
+            EmojiOutcome::Deleted,
+            EmojiOutcome::SkippedExisting,
+            EmojiOutcome::SkippedStandardShortcode,
+            EmojiOutcome::Failed(String::new()),
+        ];
+
+        if self.dry_run {
+            writeln!(f, "Dry run; nothing was changed. Planned changes:")?;
+            for result in self
+                .emojis
+                .iter()
+                .filter(|result| result.outcome.is_change())
+            {
+                write!(
+                    f,
+                    "  {} {}: {}",
+                    "+".green(),
+                    result.name,
+                    result.outcome.label(true)
+                )?;
+                match &result.outcome {
+                    EmojiOutcome::Renamed(new_name) => writeln!(f, " ({})", new_name)?,
+                    _ => writeln!(f)?,
+                }
+            }
+        }
+        writeln!(f, "Summary:")?;
+        for outcome in &rows {
+            writeln!(
+                f,
+                "  {:<34}{:>6}",
+                outcome.label(self.dry_run),
+                self.count(outcome)
+            )?;
+        }
+        if self.failures().next().is_some() {
+            writeln!(f, "Failed emojis:")?;
+            for (name, reason) in self.failures() {
+                writeln!(f, "  {} {}: {}", "!".red(), name, reason)?;
+            }
+        }
+        if !self.errors.is_empty() {
+            writeln!(f, "Other errors:")?;
+            for error in &self.errors {
+                writeln!(f, "  {} {}", "!".red(), error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_report() {
+        let mut report = RunReport::new();
+        report.record("parrot", EmojiOutcome::Uploaded);
+        report.record("seal", EmojiOutcome::SkippedStandardShortcode);
+        assert!(!report.has_failures());
+
+        report.record("blob", EmojiOutcome::Failed(String::from("error_too_big")));
+        assert_eq!(report.count(&EmojiOutcome::Failed(String::new())), 1);
+        assert_eq!(
+            serde_json::to_string(&report.emojis).unwrap(),
+            r#"[{"name":"parrot","outcome":"uploaded"},{"name":"seal","outcome":"skipped_standard_shortcode"},{"name":"blob","outcome":"failed","reason":"error_too_big"}]"#
+        );
+        assert!(matches!(
+            report.into_result(),
+            Err(Error::Incomplete {
+                failed: 1,
+                errors: 0
+            })
+        ));
+    }
+}
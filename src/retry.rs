@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode};
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+pub const DEFAULT_MAX_ELAPSED_SECS: u64 = 300;
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Governs how requests sent by `SlackClient` are retried. Rate-limited responses are retried after the duration
+/// in their `retry-after` header; server errors and connection errors are retried with exponential backoff and jitter.
+/// Retrying stops once either `max_attempts` requests have been sent or waiting any longer would exceed `max_elapsed`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub max_elapsed: Duration,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            max_elapsed: Duration::from_secs(DEFAULT_MAX_ELAPSED_SECS),
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+        }
+    }
+}
+
+/// Why a request should be sent again, along with how long to wait before doing so
+#[derive(Debug)]
+pub enum RetryReason {
+    RateLimited(Duration),
+    ServerError(StatusCode, Duration),
+    ConnectionError(reqwest::Error, Duration),
+}
+
+impl RetryReason {
+    pub fn wait_time(&self) -> Duration {
+        match self {
+            Self::RateLimited(wait_time)
+            | Self::ServerError(_, wait_time)
+            | Self::ConnectionError(_, wait_time) => *wait_time,
+        }
+    }
+}
+
+impl std::fmt::Display for RetryReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RateLimited(_) => write!(f, "rate limited"),
+            Self::ServerError(status, _) => write!(f, "server responded with {}", status),
+            Self::ConnectionError(e, _) => write!(f, "connection error: {}", e),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, max_elapsed: Duration) -> Self {
+        Self {
+            max_attempts,
+            max_elapsed,
+            ..Default::default()
+        }
+    }
+
+    /// Exponential backoff with "equal jitter": half of the exponential delay is fixed and the other half is random,
+    /// so that concurrent clients spread out without any retry happening immediately.
+    pub fn backoff(&self, retry_number: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry_number.saturating_sub(1)))
+            .min(self.max_backoff);
+        let half = exponential / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+
+    /// Decides whether the result of sending a request is final or whether the request should be sent again
+    pub fn classify(
+        &self,
+        result: reqwest::Result<Response>,
+        retry_number: u32,
+    ) -> Result<Response, RetryOutcome> {
+        match result {
+            Ok(response) => {
+                if let Some(wait_time) = Self::parse_retry_after(&response) {
+                    Err(RetryOutcome::Retry(RetryReason::RateLimited(wait_time)))
+                } else if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                    Err(RetryOutcome::Retry(RetryReason::RateLimited(
+                        self.backoff(retry_number),
+                    )))
+                } else if response.status().is_server_error() {
+                    Err(RetryOutcome::Retry(RetryReason::ServerError(
+                        response.status(),
+                        self.backoff(retry_number),
+                    )))
+                } else {
+                    Ok(response)
+                }
+            }
+            Err(e) if e.is_connect() || e.is_timeout() || e.is_request() => Err(
+                RetryOutcome::Retry(RetryReason::ConnectionError(e, self.backoff(retry_number))),
+            ),
+            Err(e) => Err(RetryOutcome::Fail(e)),
+        }
+    }
+
+    fn parse_retry_after(response: &Response) -> Option<Duration> {
+        response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+    }
+}
+
+#[derive(Debug)]
+pub enum RetryOutcome {
+    Retry(RetryReason),
+    Fail(reqwest::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_is_exponential_and_capped() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        let first = policy.backoff(1);
+        assert!(first >= Duration::from_secs(1) && first <= Duration::from_secs(2));
+        let third = policy.backoff(3);
+        assert!(third >= Duration::from_secs(4) && third <= Duration::from_secs(8));
+        let tenth = policy.backoff(10);
+        assert!(tenth >= Duration::from_secs(5) && tenth <= Duration::from_secs(10));
+    }
+}
@@ -1,6 +1,6 @@
-use std::error::Error;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 use futures::stream::StreamExt;
 use log::{info, trace};
@@ -8,9 +8,10 @@ use reqwest::header::HeaderValue;
 use reqwest::{
     header::COOKIE,
     multipart::{Form, Part},
-    Client, RequestBuilder,
+    Client, RequestBuilder, Response, Url,
 };
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use tokio::fs::{self, File};
 use tokio::io::AsyncWriteExt;
 use tokio::time::sleep;
@@ -18,6 +19,10 @@ use urlencoding::encode;
 
 use crate::archive::EmojiFile;
 use crate::emoji::Emoji;
+use crate::error::{Error, Result};
+use crate::retry::{RetryOutcome, RetryPolicy, RetryReason};
+
+pub const DEFAULT_WRITE_DELAY_MS: u64 = 1000;
 
 trait RequestBuilderExt {
     fn add_slack_session_cookie(self, session_cookie: &str) -> Self;
@@ -38,6 +43,11 @@ pub struct SlackClient {
     pub token: String,
     pub session_cookie: String,
     pub base_url: String,
+    /// If set, emoji images are downloaded from this URL instead of from the host in the URL Slack returns
+    pub emoji_cdn_url: Option<Url>,
+    pub retry_policy: RetryPolicy,
+    /// How long to wait after each request that adds an emoji, to help avoid consistently hitting a rate limit
+    pub write_delay: Duration,
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,36 +73,283 @@ enum FetchCustomEmojiPageResponseKind {
     },
 }
 
+/// Who a set of credentials belongs to, according to auth.test
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthIdentity {
+    pub user: String,
+    pub user_id: String,
+    pub team: String,
+    pub team_id: String,
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AuthTestResponseKind {
+    IdentityResponse(AuthIdentity),
+    ErrorResponse { error: String },
+}
+
+/// The result of `SlackClient::check_credentials`
+#[derive(Debug, Clone)]
+pub struct CredentialCheck {
+    pub identity: AuthIdentity,
+    pub can_list_emojis: bool,
+    /// `None` if not probed, since probing sends a request to emoji.add
+    pub can_add_emojis: Option<bool>,
+}
+
+/// The SHA-256 hash (hex-encoded) and size in bytes of an image downloaded by `SlackClient::download`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadedImage {
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Enough of the start of an image to tell its format
+const IMAGE_HEADER_LENGTH: usize = 12;
+
+/// Added to the name of an image while it downloads
+pub(crate) const PARTIAL_DOWNLOAD_SUFFIX: &str = ".part";
+
+/// Tells apart the temporary files of downloads to the same path that run at once, such as those of an emoji and an
+/// alias for it, which share an image
+static NEXT_DOWNLOAD_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies the format of an image by its first bytes (its "magic bytes"), if it is one that Slack accepts for
+/// emojis
+fn detect_image_format(header: &[u8]) -> Option<&'static str> {
+    if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if header.starts_with(b"\xff\xd8\xff") {
+        Some("jpeg")
+    } else if header.len() >= 12 && header.starts_with(b"RIFF") && &header[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+/// Error codes that Slack responds with when the credentials are valid but not allowed to do something
+const PERMISSION_ERROR_CODES: [&str; 8] = [
+    "access_denied",
+    "ekm_access_denied",
+    "missing_scope",
+    "no_permission",
+    "not_allowed_token_type",
+    "not_authorized",
+    "restricted_action",
+    "team_access_not_granted",
+];
+
+fn is_permission_error(code: &str) -> bool {
+    PERMISSION_ERROR_CODES.contains(&code)
+}
+
 impl SlackClient {
     pub fn new<S: Into<String>, T: AsRef<str>>(token: S, session_cookie: S, workspace: T) -> Self {
+        Self::new_with_base_url(
+            token,
+            session_cookie,
+            format!("https://{}.slack.com/api", workspace.as_ref()),
+        )
+    }
+
+    /// Like `new`, but sends API requests to `base_url` (e.g. "http://localhost:8080/api") instead of deriving the
+    /// URL from a workspace name
+    pub fn new_with_base_url<S: Into<String>, T: Into<String>>(
+        token: S,
+        session_cookie: S,
+        base_url: T,
+    ) -> Self {
         Self {
             client: Client::new(),
             token: token.into(),
             session_cookie: encode(session_cookie.into().as_str()).into(),
-            base_url: format!("https://{}.slack.com/api", workspace.as_ref()),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            emoji_cdn_url: None,
+            retry_policy: RetryPolicy::default(),
+            write_delay: Duration::from_millis(DEFAULT_WRITE_DELAY_MS),
         }
     }
 
+    pub fn with_emoji_cdn_url(mut self, emoji_cdn_url: Option<Url>) -> Self {
+        self.emoji_cdn_url = emoji_cdn_url;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_write_delay(mut self, write_delay: Duration) -> Self {
+        self.write_delay = write_delay;
+        self
+    }
+
     pub fn generate_url(&self, endpoint: &str) -> String {
         format!("{}/{}", self.base_url, endpoint)
     }
 
-    // TODO - add retry logic if rate limited
+    /// Swaps the scheme, host and port of `download_url` for those of `self.emoji_cdn_url`, if set. Any path in
+    /// `self.emoji_cdn_url` is prepended to the path of `download_url`.
+    pub fn resolve_download_url(&self, download_url: &str) -> Result<Url> {
+        let url = Url::parse(download_url).map_err(|e| {
+            Error::InvalidInput(format!("Invalid download URL {}: {}", download_url, e))
+        })?;
+        let cdn_url = match &self.emoji_cdn_url {
+            Some(cdn_url) => cdn_url,
+            None => return Ok(url),
+        };
+
+        let mut resolved = cdn_url.clone();
+        resolved.set_path(&format!(
+            "{}{}",
+            cdn_url.path().trim_end_matches('/'),
+            url.path()
+        ));
+        resolved.set_query(url.query());
+        Ok(resolved)
+    }
+
+    /// Sends the request produced by `build_request` according to `self.retry_policy`. A closure is needed since a
+    /// RequestBuilder is consumed when sent (and multipart forms cannot be cloned).
+    async fn send_with_retry<F>(&self, description: &str, build_request: F) -> Result<Response>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let started_at = Instant::now();
+        let mut attempt: u32 = 0;
+        loop {
+            attempt += 1;
+            let reason = match self
+                .retry_policy
+                .classify(build_request().send().await, attempt)
+            {
+                Ok(response) => return Ok(response),
+                Err(RetryOutcome::Fail(e)) => return Err(e.into()),
+                Err(RetryOutcome::Retry(reason)) => reason,
+            };
+
+            let wait_time = reason.wait_time();
+            if attempt >= self.retry_policy.max_attempts
+                || started_at.elapsed() + wait_time > self.retry_policy.max_elapsed
+            {
+                return Err(match reason {
+                    RetryReason::RateLimited(_) => Error::RateLimited {
+                        request: description.to_string(),
+                    },
+                    _ => Error::RetriesExhausted {
+                        request: description.to_string(),
+                        attempts: attempt,
+                        reason: reason.to_string(),
+                    },
+                });
+            }
+
+            trace!(
+                "Attempt {} of {} failed ({}); retrying in {:?}",
+                attempt,
+                description,
+                reason,
+                wait_time
+            );
+            sleep(wait_time).await;
+        }
+    }
+
+    /// Identifies the user and team that the credentials belong to
+    pub async fn auth_test(&self) -> Result<AuthIdentity> {
+        let description = "auth.test";
+        let response: AuthTestResponseKind = self
+            .send_with_retry(description, || {
+                self.client
+                    .post(self.generate_url("auth.test"))
+                    .form(&[("token", &self.token)])
+                    .add_slack_session_cookie(&self.session_cookie)
+            })
+            .await?
+            .json()
+            .await?;
+
+        match response {
+            AuthTestResponseKind::IdentityResponse(identity) => Ok(identity),
+            AuthTestResponseKind::ErrorResponse { error } => {
+                Err(Error::from_slack_error(description, None, error))
+            }
+        }
+    }
+
+    /// Calls auth.test and then probes whether the credentials can list emojis, by fetching a single emoji, and, if
+    /// `probe_add` is set, whether they can add emojis (see `probe_add_permission`)
+    pub async fn check_credentials(&self, probe_add: bool) -> Result<CredentialCheck> {
+        let identity = self.auth_test().await?;
+
+        let can_list_emojis = match self.fetch_custom_emoji_page(1, 1).await {
+            Ok(_) => true,
+            Err(Error::SlackApi { code, .. }) if is_permission_error(&code) => false,
+            Err(e) => return Err(e),
+        };
+        let can_add_emojis = if probe_add {
+            Some(self.probe_add_permission().await?)
+        } else {
+            None
+        };
+
+        Ok(CredentialCheck {
+            identity,
+            can_list_emojis,
+            can_add_emojis,
+        })
+    }
+
+    /// Probes whether the credentials can add emojis by calling emoji.add without a name, which Slack rejects either
+    /// for lack of permission or for the missing name, so nothing is ever added. This is a heuristic, since Slack does
+    /// not document the order in which it checks those, so it is only worth doing before a run that adds emojis.
+    async fn probe_add_permission(&self) -> Result<bool> {
+        let description = "emoji.add without a name to check permissions";
+        let response: StatusResponse = self
+            .send_with_retry(description, || {
+                let form = Form::new()
+                    .text("mode", "data")
+                    .text("token", self.token.clone());
+
+                self.client
+                    .post(self.generate_url("emoji.add"))
+                    .multipart(form)
+                    .add_slack_session_cookie(&self.session_cookie)
+            })
+            .await?
+            .json()
+            .await?;
+        match response.error {
+            Some(error) if is_permission_error(&error) => Ok(false),
+            Some(error) => match Error::from_slack_error(description, None, error) {
+                e @ (Error::Auth { .. } | Error::RateLimited { .. }) => Err(e),
+                _ => Ok(true),
+            },
+            None => Ok(true),
+        }
+    }
+
     pub async fn fetch_custom_emoji_page(
         &self,
         curr_page: u16,
         num_emojis_per_page: u8,
-    ) -> Result<(Vec<Emoji>, u16), Box<dyn Error>> {
+    ) -> Result<(Vec<Emoji>, u16)> {
+        let count = num_emojis_per_page.to_string();
+        let page = curr_page.to_string();
+        let description = format!("emoji.adminList for page {}", curr_page);
         let response: FetchCustomEmojiPageResponseKind = self
-            .client
-            .post(&self.generate_url("emoji.adminList"))
-            .form(&[
-                ("token", &self.token),
-                ("count", &num_emojis_per_page.to_string()),
-                ("page", &curr_page.to_string()),
-            ])
-            .add_slack_session_cookie(&self.session_cookie)
-            .send()
+            .send_with_retry(&description, || {
+                self.client
+                    .post(self.generate_url("emoji.adminList"))
+                    .form(&[("token", &self.token), ("count", &count), ("page", &page)])
+                    .add_slack_session_cookie(&self.session_cookie)
+            })
             .await?
             .json()
             .await?;
@@ -101,157 +358,203 @@ impl SlackClient {
             FetchCustomEmojiPageResponseKind::EmojiResponse { emojis, paging } => {
                 Ok((emojis, paging.pages))
             }
-            FetchCustomEmojiPageResponseKind::ErrorResponse { error } => Err(error.into()),
+            FetchCustomEmojiPageResponseKind::ErrorResponse { error } => {
+                Err(Error::from_slack_error(description, None, error))
+            }
         }
     }
 
+    /// Downloads an emoji image to `path`. The image is written to a temporary file next to `path` first, and only
+    /// renamed to `path` once the whole image has arrived and looks like an image, so `path` is never left with a
+    /// partial image.
     pub async fn download<P: AsRef<Path>>(
         &self,
         download_url: &str,
         path: P,
-    ) -> Result<(), Box<dyn Error>> {
-        let mut emoji_file = File::create(path).await?;
-        let mut stream = self.client.get(download_url).send().await?.bytes_stream();
-
-        while let Some(Ok(chunk)) = stream.next().await {
-            emoji_file.write_all(&chunk).await?;
-        }
-        emoji_file.flush().await?;
+    ) -> Result<DownloadedImage> {
+        let path = path.as_ref();
+        let download_url = self.resolve_download_url(download_url)?;
+        let response = self
+            .send_with_retry(&format!("download of {}", download_url), || {
+                self.client.get(download_url.clone())
+            })
+            .await?
+            .error_for_status()?;
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(format!(
+            ".{}{}",
+            NEXT_DOWNLOAD_ID.fetch_add(1, Ordering::Relaxed),
+            PARTIAL_DOWNLOAD_SUFFIX
+        ));
+        let temp_path = PathBuf::from(temp_path);
+        let downloaded_image =
+            match Self::write_verified_image(response, &download_url, &temp_path).await {
+                Ok(downloaded_image) => downloaded_image,
+                Err(e) => {
+                    // The partial image is of no use to anyone
+                    let _ = fs::remove_file(&temp_path).await;
+                    return Err(e);
+                }
+            };
+        fs::rename(&temp_path, path).await?;
 
-        Ok(())
+        Ok(downloaded_image)
     }
 
-    pub async fn upload(
-        &self,
-        emoji_file: &EmojiFile,
-        emoji_filepath: PathBuf,
-    ) -> Result<(), Box<dyn Error>> {
-        let mut try_count: u8 = 0;
-        let result = loop {
-            // form needs to be recreated on each iteration of the loop since RequestBuilder moves it
-            let form = Form::new()
-                .text("mode", "data")
-                // clones are needed here because the values passed to reqwest::multipart::Part's text and file_name methods
-                // are bound by Into<Cow<'static, str>>, so any references passed in would need to have a 'static lifetime.
-                .text("name", emoji_file.emoji.name.clone())
-                .part(
-                    "image",
-                    Part::bytes(fs::read(emoji_filepath.clone()).await?)
-                        .file_name(emoji_file.filename.clone()),
-                )
-                .text("token", self.token.clone());
-
-            let response = self
-                .client
-                .post(&self.generate_url("emoji.add"))
-                .multipart(form)
-                .add_slack_session_cookie(&self.session_cookie)
-                .send()
-                .await?;
-
-            // TODO: if multiple Slack requests rely on handling rate-limiting, could this be better abstracted with a macro?
-            if let Some(wait_time_s) = response.headers().get("retry-after") {
-                if try_count == 3 {
-                    break Err(format!(
-                        "Could not successfully upload emoji within 3 tries, skipping: {:?}",
-                        emoji_file
-                    ));
-                };
-                try_count += 1;
-                // TODO: better error handling / maybe a better way to go about this?
-                let wait_time_s: u64 = wait_time_s.to_str()?.parse()?;
-                trace!(
-                    "Hit rate-limit on emoji.add for emoji {}; retrying in {} seconds",
-                    emoji_file.emoji.name,
-                    wait_time_s
-                );
-                sleep(Duration::from_secs(wait_time_s)).await;
-                continue;
+    async fn write_verified_image(
+        response: Response,
+        download_url: &Url,
+        path: &Path,
+    ) -> Result<DownloadedImage> {
+        let invalid_image = |reason: String| Error::InvalidImage {
+            url: download_url.to_string(),
+            reason,
+        };
+        let expected_length = response.content_length();
+        let mut stream = response.bytes_stream();
+        let mut file = File::create(path).await?;
+        let mut header = Vec::with_capacity(IMAGE_HEADER_LENGTH);
+        let mut length: u64 = 0;
+        let mut hasher = Sha256::new();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            if header.len() < IMAGE_HEADER_LENGTH {
+                let needed = (IMAGE_HEADER_LENGTH - header.len()).min(chunk.len());
+                header.extend_from_slice(&chunk[..needed]);
+            }
+            length += chunk.len() as u64;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+        }
+        file.sync_all().await?;
+
+        if let Some(expected_length) = expected_length {
+            if length != expected_length {
+                return Err(invalid_image(format!(
+                    "received {} of {} bytes",
+                    length, expected_length
+                )));
             }
+        }
+        if detect_image_format(&header).is_none() {
+            return Err(invalid_image(String::from(
+                "contents are not a PNG, GIF, JPEG or WebP image",
+            )));
+        }
 
-            break Ok(response.json::<StatusResponse>().await?);
-        };
+        Ok(DownloadedImage {
+            sha256: hex::encode(hasher.finalize()),
+            size: length,
+        })
+    }
 
-        // Trying to help avoid consistently hitting a rate limit at a certain point
-        sleep(Duration::from_secs(1)).await;
+    /// Fetches an emoji image into memory
+    pub async fn fetch_image(&self, download_url: &str) -> Result<Vec<u8>> {
+        let download_url = self.resolve_download_url(download_url)?;
+        let response = self
+            .send_with_retry(&format!("download of {}", download_url), || {
+                self.client.get(download_url.clone())
+            })
+            .await?
+            .error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
 
-        match result {
-            Ok(response) => {
-                if let Some(error_msg) = response.error {
-                    Err(format!(
-                        "Failed to upload emoji {} for reason: {}",
-                        emoji_file.emoji.name, error_msg
+    pub async fn upload(&self, emoji_file: &EmojiFile, emoji_filepath: PathBuf) -> Result<()> {
+        let image = fs::read(emoji_filepath).await?;
+        self.upload_image(&emoji_file.emoji.name, &emoji_file.filename, image)
+            .await
+    }
+
+    pub async fn upload_image(&self, name: &str, filename: &str, image: Vec<u8>) -> Result<()> {
+        let description = format!("emoji.add for emoji {}", name);
+        let response: StatusResponse = self
+            .send_with_retry(&description, || {
+                // form needs to be recreated for each attempt since RequestBuilder moves it
+                let form = Form::new()
+                    .text("mode", "data")
+                    // clones are needed here because the values passed to reqwest::multipart::Part's text and file_name methods
+                    // are bound by Into<Cow<'static, str>>, so any references passed in would need to have a 'static lifetime.
+                    .text("name", name.to_string())
+                    .part(
+                        "image",
+                        Part::bytes(image.clone()).file_name(filename.to_string()),
                     )
-                    .into())
-                } else {
-                    info!("Uploaded emoji: {:?}", emoji_file);
-                    Ok(())
-                }
-            }
-            Err(e) => Err(e.into()),
+                    .text("token", self.token.clone());
+
+                self.client
+                    .post(self.generate_url("emoji.add"))
+                    .multipart(form)
+                    .add_slack_session_cookie(&self.session_cookie)
+            })
+            .await?
+            .json()
+            .await?;
+
+        sleep(self.write_delay).await;
+
+        if let Some(error) = response.error {
+            Err(Error::from_slack_error(description, Some(name), error))
+        } else {
+            info!("Uploaded emoji: {} ({})", name, filename);
+            Ok(())
         }
     }
 
-    pub async fn add_alias(&self, name: &str, alias_for: &str) -> Result<(), Box<dyn Error>> {
-        let mut try_count: u8 = 0;
-        let result = loop {
-            // form needs to be recreated on each iteration of the loop since RequestBuilder moves it
-            let form = Form::new()
-                .text("mode", "alias")
-                // clones are needed here because the values passed to reqwest::multipart::Part's text and file_name methods
-                // are bound by Into<Cow<'static, str>>, so any references passed in would need to have a 'static lifetime.
-                .text("name", name.to_string())
-                .text("alias_for", alias_for.to_string())
-                .text("token", self.token.clone());
-
-            let response = self
-                .client
-                .post(&self.generate_url("emoji.add"))
-                .multipart(form)
-                .add_slack_session_cookie(&self.session_cookie)
-                .send()
-                .await?;
-
-            // TODO: if multiple Slack requests rely on handling rate-limiting, could this be better abstracted with a macro?
-            if let Some(wait_time_s) = response.headers().get("retry-after") {
-                if try_count == 3 {
-                    break Err(format!(
-                        "Could not successfully add alias '{}' for '{}' within 3 tries; skipping",
-                        name, alias_for
-                    ));
-                };
-                try_count += 1;
-                // TODO: better error handling / maybe a better way to go about this?
-                let wait_time_s: u64 = wait_time_s.to_str()?.parse()?;
-                trace!(
-                    "Hit rate-limit on emoji.add for adding alias '{}' for '{}'; retrying in {} seconds",
-                    name, alias_for,
-                    wait_time_s
-                );
-                sleep(Duration::from_secs(wait_time_s)).await;
-                continue;
-            }
+    pub async fn add_alias(&self, name: &str, alias_for: &str) -> Result<()> {
+        let description = format!("emoji.add for adding alias '{}' for '{}'", name, alias_for);
+        let response: StatusResponse = self
+            .send_with_retry(&description, || {
+                // form needs to be recreated for each attempt since RequestBuilder moves it
+                let form = Form::new()
+                    .text("mode", "alias")
+                    // clones are needed here because the values passed to reqwest::multipart::Part's text and file_name methods
+                    // are bound by Into<Cow<'static, str>>, so any references passed in would need to have a 'static lifetime.
+                    .text("name", name.to_string())
+                    .text("alias_for", alias_for.to_string())
+                    .text("token", self.token.clone());
+
+                self.client
+                    .post(self.generate_url("emoji.add"))
+                    .multipart(form)
+                    .add_slack_session_cookie(&self.session_cookie)
+            })
+            .await?
+            .json()
+            .await?;
 
-            break Ok(response.json::<StatusResponse>().await?);
-        };
+        sleep(self.write_delay).await;
+
+        if let Some(error) = response.error {
+            Err(Error::from_slack_error(description, Some(name), error))
+        } else {
+            info!("Added alias '{}' for '{}'", name, alias_for);
+            Ok(())
+        }
+    }
 
-        // Trying to help avoid consistently hitting a rate limit at a certain point
-        sleep(Duration::from_secs(1)).await;
+    pub async fn remove(&self, name: &str) -> Result<()> {
+        let description = format!("emoji.remove for emoji {}", name);
+        let response: StatusResponse = self
+            .send_with_retry(&description, || {
+                self.client
+                    .post(self.generate_url("emoji.remove"))
+                    .form(&[("token", self.token.as_str()), ("name", name)])
+                    .add_slack_session_cookie(&self.session_cookie)
+            })
+            .await?
+            .json()
+            .await?;
 
-        match result {
-            Ok(response) => {
-                if let Some(error_msg) = response.error {
-                    Err(format!(
-                        "Failed to add alias '{}' for '{}' for reason: {}",
-                        name, alias_for, error_msg
-                    )
-                    .into())
-                } else {
-                    info!("Added alias '{}' for '{}'", name, alias_for);
-                    Ok(())
-                }
-            }
-            Err(e) => Err(e.into()),
+        sleep(self.write_delay).await;
+
+        if let Some(error) = response.error {
+            Err(Error::from_slack_error(description, Some(name), error))
+        } else {
+            info!("Removed emoji: {}", name);
+            Ok(())
         }
     }
 }
@@ -259,7 +562,70 @@ impl SlackClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::archive::EmojiDirectory;
+    use crate::emoji::new_emoji_stream;
     use chrono::prelude::*;
+    use std::sync::Arc;
+
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    #[test]
+    fn test_client_and_streams_are_send_sync() {
+        let client = Arc::new(SlackClient::new("token", "cookie", "workspace"));
+        assert_send_sync(&client);
+        assert_send_sync(&new_emoji_stream(client, None));
+        assert_send_sync(&EmojiDirectory::new("emojis").stream_emoji_files());
+    }
+
+    #[test]
+    fn test_detect_image_format() {
+        assert_eq!(
+            detect_image_format(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            Some("png")
+        );
+        assert_eq!(detect_image_format(b"GIF89a\x01\0\x01\0"), Some("gif"));
+        assert_eq!(
+            detect_image_format(b"\xff\xd8\xff\xe0\0\x10JFIF"),
+            Some("jpeg")
+        );
+        assert_eq!(detect_image_format(b"RIFF\x24\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(detect_image_format(b"RIFF\x24\0\0\0WAVEfmt "), None);
+        assert_eq!(detect_image_format(b"\x89PNG"), None);
+        assert_eq!(detect_image_format(b"<!DOCTYPE html>"), None);
+    }
+
+    #[test]
+    fn test_resolve_download_url() {
+        let download_url = "https://emoji.slack-edge.com/T03C6ES54/zuck/6f285f21ac5f972b.png";
+        let mut client =
+            SlackClient::new_with_base_url("token", "cookie", "http://127.0.0.1:8080/api/");
+        assert_eq!(client.base_url, "http://127.0.0.1:8080/api");
+        assert_eq!(
+            client.resolve_download_url(download_url).unwrap().as_str(),
+            download_url
+        );
+
+        client = client.with_emoji_cdn_url(Some(Url::parse("http://127.0.0.1:8080").unwrap()));
+        assert_eq!(
+            client.resolve_download_url(download_url).unwrap().as_str(),
+            "http://127.0.0.1:8080/T03C6ES54/zuck/6f285f21ac5f972b.png"
+        );
+
+        client = client.with_emoji_cdn_url(Some(Url::parse("http://127.0.0.1:8080/cdn").unwrap()));
+        assert_eq!(
+            client.resolve_download_url(download_url).unwrap().as_str(),
+            "http://127.0.0.1:8080/cdn/T03C6ES54/zuck/6f285f21ac5f972b.png"
+        );
+    }
+
+    #[test]
+    fn test_is_permission_error() {
+        assert!(is_permission_error("not_allowed_token_type"));
+        assert!(is_permission_error("missing_scope"));
+        // Codes that merely mention permissions or admins are not permission errors
+        assert!(!is_permission_error("invalid_admin_setting"));
+        assert!(!is_permission_error("error_name_taken"));
+    }
 
     #[test]
     fn test_emoji_response_from_slack_api() {
@@ -0,0 +1,270 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use colored::Colorize;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, read_dir};
+
+use crate::archive::{
+    parse_record, EmojiDirectory, EmojiFile, EMOJI_METADATA_FILENAME, HISTORY_FILENAME,
+    MANIFEST_FILENAME, TEMP_FILE_SUFFIX,
+};
+use crate::error::{Error, Result};
+use crate::journal::UPLOAD_JOURNAL_FILENAME;
+use crate::slack::PARTIAL_DOWNLOAD_SUFFIX;
+
+/// Files in an `EmojiDirectory` that are not emoji images
+const NON_IMAGE_FILENAMES: [&str; 4] = [
+    EMOJI_METADATA_FILENAME,
+    HISTORY_FILENAME,
+    MANIFEST_FILENAME,
+    UPLOAD_JOURNAL_FILENAME,
+];
+
+/// Suffixes of files left behind by a write that was interrupted, which are not part of the archive either
+const LEFTOVER_FILE_SUFFIXES: [&str; 2] = [TEMP_FILE_SUFFIX, PARTIAL_DOWNLOAD_SUFFIX];
+
+/// Something wrong with an `EmojiDirectory`. Line numbers count from 1.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ArchiveProblem {
+    MalformedLine {
+        line: usize,
+        error: String,
+    },
+    /// More than one line of metadata has the same emoji name
+    DuplicateName {
+        name: String,
+        lines: Vec<usize>,
+    },
+    MissingFile {
+        name: String,
+        filename: String,
+    },
+    SizeMismatch {
+        name: String,
+        filename: String,
+        recorded: u64,
+        actual: u64,
+    },
+    HashMismatch {
+        name: String,
+        filename: String,
+        recorded: String,
+        actual: String,
+    },
+    /// An image that no line of metadata refers to
+    OrphanedFile {
+        filename: String,
+    },
+}
+
+impl fmt::Display for ArchiveProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MalformedLine { line, error } => {
+                write!(f, "line {} is malformed: {}", line, error)
+            }
+            Self::DuplicateName { name, lines } => write!(
+                f,
+                "{} appears on more than one line ({})",
+                name,
+                lines
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::MissingFile { name, filename } => {
+                write!(f, "{}: image {} is missing", name, filename)
+            }
+            Self::SizeMismatch {
+                name,
+                filename,
+                recorded,
+                actual,
+            } => write!(
+                f,
+                "{}: image {} is {} bytes, but {} bytes were recorded",
+                name, filename, actual, recorded
+            ),
+            Self::HashMismatch {
+                name,
+                filename,
+                recorded,
+                actual,
+            } => write!(
+                f,
+                "{}: image {} has SHA-256 hash {}, but {} was recorded",
+                name, filename, actual, recorded
+            ),
+            Self::OrphanedFile { filename } => {
+                write!(f, "{} is not referred to by any line of metadata", filename)
+            }
+        }
+    }
+}
+
+/// The result of checking an `EmojiDirectory` with `verify_directory`
+#[derive(Debug, Default, Serialize)]
+pub struct VerificationReport {
+    pub lines_checked: usize,
+    /// Images whose hash could be checked, since one was recorded for them
+    pub images_hashed: usize,
+    pub problems: Vec<ArchiveProblem>,
+}
+
+impl VerificationReport {
+    /// Fails if any problem was found, so that the CLI exits with a non-zero code
+    pub fn into_result(self) -> Result<Self> {
+        if self.problems.is_empty() {
+            Ok(self)
+        } else {
+            Err(Error::ArchiveProblems {
+                count: self.problems.len(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Checked {} line(s) of metadata and the hashes of {} image(s)",
+            self.lines_checked, self.images_hashed
+        )?;
+        if self.problems.is_empty() {
+            return writeln!(f, "No problems found");
+        }
+        writeln!(f, "Problems ({}):", self.problems.len())?;
+        for problem in &self.problems {
+            writeln!(f, "  {} {}", "!".red(), problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks every line of metadata in `directory` against the images in it, and the images against the metadata
+pub async fn verify_directory(directory: &EmojiDirectory) -> Result<VerificationReport> {
+    directory.ensure_is_directory().await?;
+    let mut report = VerificationReport::default();
+
+    let format_version = directory.read_format_version().await?;
+    let metadata = fs::read_to_string(directory.get_metadata_filepath()).await?;
+    let mut emoji_files: Vec<EmojiFile> = Vec::new();
+    let mut lines_by_name: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, line) in metadata.lines().enumerate() {
+        report.lines_checked += 1;
+        match parse_record(line, format_version) {
+            Ok(emoji_file) => {
+                lines_by_name
+                    .entry(emoji_file.emoji.name.clone())
+                    .or_default()
+                    .push(index + 1);
+                emoji_files.push(emoji_file);
+            }
+            Err(e) => report.problems.push(ArchiveProblem::MalformedLine {
+                line: index + 1,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    let mut duplicates: Vec<(String, Vec<usize>)> = lines_by_name
+        .into_iter()
+        .filter(|(_, lines)| lines.len() > 1)
+        .collect();
+    duplicates.sort();
+    report.problems.extend(
+        duplicates
+            .into_iter()
+            .map(|(name, lines)| ArchiveProblem::DuplicateName { name, lines }),
+    );
+
+    for emoji_file in &emoji_files {
+        // Aliases share the image of the emoji they are for, if they have one at all
+        if !emoji_file.emoji.alias_for.is_empty() {
+            continue;
+        }
+        if let Some(problem) = verify_image(directory, emoji_file, &mut report).await? {
+            report.problems.push(problem);
+        }
+    }
+
+    // Images of earlier revisions are kept, so they are not orphaned either
+    let history = directory.read_history().await?;
+    let referenced_filenames: HashSet<&str> = emoji_files
+        .iter()
+        .chain(&history)
+        .map(|emoji_file| emoji_file.filename.as_str())
+        .collect();
+    let mut orphaned_filenames = Vec::new();
+    let mut entries = read_dir(directory.path()).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let filename = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type().await?.is_file()
+            && !NON_IMAGE_FILENAMES.contains(&filename.as_str())
+            && !LEFTOVER_FILE_SUFFIXES
+                .iter()
+                .any(|suffix| filename.ends_with(suffix))
+            && !referenced_filenames.contains(filename.as_str())
+        {
+            orphaned_filenames.push(filename);
+        }
+    }
+    orphaned_filenames.sort();
+    report.problems.extend(
+        orphaned_filenames
+            .into_iter()
+            .map(|filename| ArchiveProblem::OrphanedFile { filename }),
+    );
+
+    Ok(report)
+}
+
+async fn verify_image(
+    directory: &EmojiDirectory,
+    emoji_file: &EmojiFile,
+    report: &mut VerificationReport,
+) -> Result<Option<ArchiveProblem>> {
+    let name = || emoji_file.emoji.name.clone();
+    let filename = || emoji_file.filename.clone();
+
+    let image = match fs::read(directory.get_emoji_filepath(emoji_file)).await {
+        Ok(image) => image,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Some(ArchiveProblem::MissingFile {
+                name: name(),
+                filename: filename(),
+            }))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(recorded) = emoji_file.size {
+        if image.len() as u64 != recorded {
+            return Ok(Some(ArchiveProblem::SizeMismatch {
+                name: name(),
+                filename: filename(),
+                recorded,
+                actual: image.len() as u64,
+            }));
+        }
+    }
+    if let Some(recorded) = &emoji_file.sha256 {
+        report.images_hashed += 1;
+        let actual = hex::encode(Sha256::digest(&image));
+        if &actual != recorded {
+            return Ok(Some(ArchiveProblem::HashMismatch {
+                name: name(),
+                filename: filename(),
+                recorded: recorded.clone(),
+                actual,
+            }));
+        }
+    }
+
+    Ok(None)
+}
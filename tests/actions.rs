@@ -0,0 +1,1187 @@
+mod common;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use futures::pin_mut;
+use futures::stream::StreamExt;
+use regex::Regex;
+use slack_emoji::error::Error;
+use slack_emoji::filter::{AliasSelection, EmojiFilter, NamePattern};
+use slack_emoji::report::EmojiOutcome;
+use slack_emoji::verify::{verify_directory, ArchiveProblem};
+use slack_emoji::{
+    delete, diff, download, preflight, rename, sync, upload, ConflictStrategy, DownloadOptions,
+    EmojiDirectory, EmojiFile, EmojiStreamParameters, RequiredAccess, RunReport, SlackClient,
+    UploadOptions,
+};
+use tempfile::tempdir;
+
+use common::{MockSlackServer, MOCK_TOKEN};
+
+fn png(name: &str) -> Vec<u8> {
+    let mut bytes = b"\x89PNG\r\n\x1a\n".to_vec();
+    bytes.extend_from_slice(name.as_bytes());
+    bytes
+}
+
+async fn read_archive(directory: &EmojiDirectory) -> Vec<EmojiFile> {
+    let stream = directory.stream_emoji_files();
+    pin_mut!(stream);
+    let mut emoji_files = Vec::new();
+    while let Some(emoji_file) = stream.next().await {
+        emoji_files.push(emoji_file.unwrap());
+    }
+    emoji_files
+}
+
+#[tokio::test]
+async fn test_preflight_checks_credentials_and_permissions() {
+    let server = MockSlackServer::start().await;
+    let check = preflight(&server.client(), RequiredAccess::ListAndAdd)
+        .await
+        .unwrap();
+    assert_eq!(check.identity.user, "mock.user");
+    assert_eq!(check.identity.team, "Mock Team");
+    assert!(check.can_list_emojis && check.can_add_emojis == Some(true));
+    // The probe for adding emojis must not add anything
+    assert!(server.emojis().is_empty());
+
+    // Nor is it sent at all unless adding emojis is required
+    let requests_before = server.request_count("emoji.add");
+    let check = preflight(&server.client(), RequiredAccess::List)
+        .await
+        .unwrap();
+    assert!(check.can_add_emojis.is_none());
+    assert_eq!(server.request_count("emoji.add"), requests_before);
+
+    let client = SlackClient::new_with_base_url(MOCK_TOKEN, "expired", server.api_base_url());
+    assert!(matches!(
+        preflight(&client, RequiredAccess::List).await,
+        Err(Error::Auth { code }) if code == "invalid_auth"
+    ));
+
+    server.deny_permission("emoji.add");
+    assert!(matches!(
+        preflight(&server.client(), RequiredAccess::ListAndAdd).await,
+        Err(Error::PermissionDenied { action, .. }) if action == "add emojis"
+    ));
+
+    server.deny_permission("emoji.adminList");
+    assert!(matches!(
+        preflight(&server.client(), RequiredAccess::List).await,
+        Err(Error::PermissionDenied { action, .. }) if action == "list emojis"
+    ));
+}
+
+#[tokio::test]
+async fn test_download_pages_through_rate_limits() {
+    let server = MockSlackServer::start().await;
+    for i in 0..7 {
+        server.add_emoji(&format!("emoji-{}", i), &png(&i.to_string()));
+    }
+    server.add_alias("emoji-alias", "emoji-3");
+    server.rate_limit_next(2, 0);
+
+    let directory = tempdir().unwrap();
+    download(
+        Arc::new(server.client()),
+        directory.path().to_str().unwrap(),
+        EmojiStreamParameters::new(1, 3, None),
+        &EmojiFilter::default(),
+        &DownloadOptions {
+            concurrency: 4,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    // 3 pages plus the 2 rate-limited attempts
+    assert_eq!(server.request_count("emoji.adminList"), 5);
+    let emoji_directory = EmojiDirectory::new(directory.path());
+    let emoji_files = read_archive(&emoji_directory).await;
+    assert_eq!(emoji_files.len(), 8);
+    for emoji_file in &emoji_files {
+        let expected = server
+            .get_image(&emoji_file.emoji.name)
+            .or_else(|| server.get_image(&emoji_file.emoji.alias_for))
+            .unwrap();
+        let actual = std::fs::read(emoji_directory.get_emoji_filepath(emoji_file)).unwrap();
+        assert_eq!(actual, expected);
+    }
+    assert_eq!(emoji_files[7].emoji.alias_for, "emoji-3");
+
+    // A second run only downloads what is new
+    server.add_emoji("emoji-new", &png("new"));
+    let report = download(
+        Arc::new(server.client()),
+        directory.path().to_str().unwrap(),
+        EmojiStreamParameters::default(),
+        &EmojiFilter::default(),
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.count(&EmojiOutcome::Downloaded), 1);
+    assert_eq!(report.count(&EmojiOutcome::SkippedExisting), 8);
+    let emoji_files = read_archive(&emoji_directory).await;
+    assert_eq!(emoji_files.len(), 9);
+    assert_eq!(emoji_files[8].emoji.name, "emoji-new");
+}
+
+#[tokio::test]
+async fn test_download_rejects_invalid_images() {
+    let server = MockSlackServer::start().await;
+    server.add_emoji("parrot", &png("parrot"));
+    server.add_emoji("half-parrot", b"<html>Service Unavailable</html>");
+
+    let directory = tempdir().unwrap();
+    let report = download(
+        Arc::new(server.client()),
+        directory.path(),
+        EmojiStreamParameters::default(),
+        &EmojiFilter::default(),
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(report.count(&EmojiOutcome::Downloaded), 1);
+    assert!(matches!(
+        &report.emojis[1].outcome,
+        EmojiOutcome::Failed(reason) if reason.contains("not a PNG")
+    ));
+    let emoji_files = read_archive(&EmojiDirectory::new(directory.path())).await;
+    assert_eq!(emoji_files.len(), 1);
+    assert_eq!(emoji_files[0].emoji.name, "parrot");
+    // Neither the image nor the temporary file it was downloaded to is left behind
+    let mut filenames: Vec<String> = std::fs::read_dir(directory.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    filenames.sort();
+    assert_eq!(filenames.len(), 3);
+    assert_eq!(filenames[0], "manifest.json");
+    assert_eq!(filenames[1], "metadata.ndjson");
+    assert!(filenames[2].starts_with("parrot-"));
+}
+
+#[tokio::test]
+async fn test_download_updates_changed_emojis() {
+    let server = MockSlackServer::start().await;
+    server.add_emoji("parrot", &png("parrot"));
+    server.add_emoji("blob", &png("blob"));
+
+    let directory = tempdir().unwrap();
+    let emoji_directory = EmojiDirectory::new(directory.path());
+    let no_filter = EmojiFilter::default();
+    let options = DownloadOptions::default();
+    let download_all = || {
+        download(
+            Arc::new(server.client()),
+            directory.path(),
+            EmojiStreamParameters::default(),
+            &no_filter,
+            &options,
+        )
+    };
+    download_all().await.unwrap();
+    let first_parrot = read_archive(&emoji_directory).await.remove(0);
+
+    server.replace_emoji("parrot", &png("new parrot"));
+    let report = download_all().await.unwrap();
+    assert_eq!(report.count(&EmojiOutcome::Updated), 1);
+    assert_eq!(report.count(&EmojiOutcome::SkippedExisting), 1);
+
+    // The new revision takes the place of the old one, which moves to the history along with its image
+    let emoji_files = read_archive(&emoji_directory).await;
+    assert_eq!(emoji_files.len(), 2);
+    assert_eq!(emoji_files[0].emoji.name, "parrot");
+    assert_eq!(emoji_files[0].revision, 2);
+    assert_ne!(emoji_files[0].filename, first_parrot.filename);
+    assert_eq!(
+        std::fs::read(emoji_directory.get_emoji_filepath(&emoji_files[0])).unwrap(),
+        png("new parrot")
+    );
+    let history = emoji_directory.read_history().await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].revision, 1);
+    assert_eq!(history[0].filename, first_parrot.filename);
+    assert_eq!(
+        std::fs::read(emoji_directory.get_emoji_filepath(&history[0])).unwrap(),
+        png("parrot")
+    );
+    assert!(verify_directory(&emoji_directory)
+        .await
+        .unwrap()
+        .problems
+        .is_empty());
+
+    let report = download_all().await.unwrap();
+    assert_eq!(report.count(&EmojiOutcome::SkippedExisting), 2);
+}
+
+#[tokio::test]
+async fn test_download_filters_emojis() {
+    let server = MockSlackServer::start().await;
+    server.add_emoji("parrot", &png("parrot"));
+    server.add_emoji("blob", &png("blob"));
+    server.add_alias("blob-alias", "blob");
+    server.add_emoji("blob-wave", &png("blob-wave"));
+
+    let directory = tempdir().unwrap();
+    let filter = EmojiFilter {
+        name_patterns: vec![NamePattern::Regex(Regex::new("^blob").unwrap())],
+        aliases: AliasSelection::Exclude,
+        created_before: Some(
+            Utc.timestamp_opt(server.get_emoji("blob-wave").unwrap().created, 0)
+                .unwrap(),
+        ),
+        ..Default::default()
+    };
+    let report = download(
+        Arc::new(server.client()),
+        directory.path(),
+        EmojiStreamParameters::default(),
+        &filter,
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    // Emojis that are not selected are left out of the report altogether
+    assert_eq!(report.emojis.len(), 1);
+    let emoji_files = read_archive(&EmojiDirectory::new(directory.path())).await;
+    assert_eq!(emoji_files.len(), 1);
+    assert_eq!(emoji_files[0].emoji.name, "blob");
+}
+
+#[tokio::test]
+async fn test_download_marks_deleted_emojis() {
+    let server = MockSlackServer::start().await;
+    server.add_emoji("parrot", &png("parrot"));
+    server.add_emoji("blob", &png("blob"));
+    server.add_emoji("zuck", &png("zuck"));
+
+    let directory = tempdir().unwrap();
+    let emoji_directory = EmojiDirectory::new(directory.path());
+    let no_filter = EmojiFilter::default();
+    let download_with = |stream_parameters, mark_deleted| {
+        let (client, path, filter) = (Arc::new(server.client()), directory.path(), &no_filter);
+        async move {
+            download(
+                client,
+                path,
+                stream_parameters,
+                filter,
+                &DownloadOptions {
+                    mark_deleted,
+                    ..Default::default()
+                },
+            )
+            .await
+        }
+    };
+    download_with(EmojiStreamParameters::default(), false)
+        .await
+        .unwrap();
+    server.remove_emoji("blob");
+
+    // Without the option, or with only some pages listed, nothing is marked
+    download_with(EmojiStreamParameters::default(), false)
+        .await
+        .unwrap();
+    download_with(EmojiStreamParameters::new(1, 1, Some(1)), true)
+        .await
+        .unwrap();
+    assert!(read_archive(&emoji_directory)
+        .await
+        .iter()
+        .all(|emoji_file| emoji_file.deleted_upstream_at.is_none()));
+
+    let report = download_with(EmojiStreamParameters::default(), true)
+        .await
+        .unwrap();
+    assert_eq!(report.count(&EmojiOutcome::MarkedDeleted), 1);
+    let deleted_names: Vec<String> = read_archive(&emoji_directory)
+        .await
+        .into_iter()
+        .filter(|emoji_file| emoji_file.deleted_upstream_at.is_some())
+        .map(|emoji_file| emoji_file.emoji.name)
+        .collect();
+    assert_eq!(deleted_names, ["blob"]);
+    // Emojis already marked are not marked again
+    let report = download_with(EmojiStreamParameters::default(), true)
+        .await
+        .unwrap();
+    assert_eq!(report.count(&EmojiOutcome::MarkedDeleted), 0);
+
+    let destination = MockSlackServer::start().await;
+    let report = upload(
+        Arc::new(destination.client()),
+        directory.path(),
+        &EmojiFilter::default(),
+        &UploadOptions::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.count(&EmojiOutcome::Uploaded), 2);
+    assert!(destination.get_emoji("blob").is_none());
+
+    let report = upload(
+        Arc::new(destination.client()),
+        directory.path(),
+        &EmojiFilter::default(),
+        &UploadOptions {
+            include_deleted: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.count(&EmojiOutcome::Uploaded), 1);
+    assert_eq!(destination.get_image("blob").unwrap(), png("blob"));
+
+    // An emoji marked as deleted that is listed again unchanged is no longer marked
+    emoji_directory
+        .mark_deleted_upstream(&HashSet::from([String::from("zuck")]), Utc::now())
+        .await
+        .unwrap();
+    let report = download_with(EmojiStreamParameters::default(), false)
+        .await
+        .unwrap();
+    assert_eq!(report.count(&EmojiOutcome::Reappeared), 1);
+    assert_eq!(report.count(&EmojiOutcome::Downloaded), 0);
+    let deleted_names: Vec<String> = read_archive(&emoji_directory)
+        .await
+        .into_iter()
+        .filter(|emoji_file| emoji_file.deleted_upstream_at.is_some())
+        .map(|emoji_file| emoji_file.emoji.name)
+        .collect();
+    assert_eq!(deleted_names, ["blob"]);
+}
+
+#[tokio::test]
+async fn test_verify_finds_archive_problems() {
+    let server = MockSlackServer::start().await;
+    server.add_emoji("parrot", &png("parrot"));
+    server.add_emoji("blob", &png("blob"));
+    server.add_emoji("zuck", &png("zuck"));
+    server.add_alias("parrot-alias", "parrot");
+    let directory = server.download_archive().await;
+
+    let emoji_directory = EmojiDirectory::new(directory.path());
+    let emoji_files = read_archive(&emoji_directory).await;
+    assert_eq!(emoji_files[0].size, Some(png("parrot").len() as u64));
+    assert_eq!(emoji_files[0].sha256.as_ref().unwrap().len(), 64);
+    // Aliases share the image of the emoji they are for
+    assert_eq!(emoji_files[3].sha256, emoji_files[0].sha256);
+    let report = verify_directory(&emoji_directory).await.unwrap();
+    assert_eq!(report.images_hashed, 3);
+    assert!(report.problems.is_empty());
+
+    let [parrot, blob, zuck, _] = &emoji_files[..] else {
+        panic!("Expected 4 emojis in the archive");
+    };
+    std::fs::write(emoji_directory.get_emoji_filepath(parrot), png("rotted")).unwrap();
+    std::fs::write(emoji_directory.get_emoji_filepath(blob), png("blob!")).unwrap();
+    std::fs::remove_file(emoji_directory.get_emoji_filepath(zuck)).unwrap();
+    std::fs::write(directory.path().join("stray.png"), png("stray")).unwrap();
+    // Left behind by an interrupted download and metadata rewrite, which are not orphaned images
+    let mut partial_filename = emoji_directory.get_emoji_filepath(parrot).into_os_string();
+    partial_filename.push(".7.part");
+    std::fs::write(partial_filename, png("parro")).unwrap();
+    std::fs::write(
+        directory.path().join("metadata.ndjson.tmp"),
+        "{\"name\": \"par",
+    )
+    .unwrap();
+    let mut metadata = std::fs::read_to_string(emoji_directory.get_metadata_filepath()).unwrap();
+    let first_line = metadata.lines().next().unwrap().to_string();
+    metadata.push_str(&format!("{}\n{{\"name\": \"half\n", first_line));
+    std::fs::write(emoji_directory.get_metadata_filepath(), metadata).unwrap();
+
+    let report = verify_directory(&emoji_directory).await.unwrap();
+    let kinds: Vec<&str> = report
+        .problems
+        .iter()
+        .map(|problem| match problem {
+            ArchiveProblem::MalformedLine { line: 6, .. } => "malformed",
+            ArchiveProblem::DuplicateName { name, lines }
+                if name == "parrot" && lines == &[1, 5] =>
+            {
+                "duplicate"
+            }
+            ArchiveProblem::HashMismatch { name, .. } if name == "parrot" => "hash",
+            ArchiveProblem::SizeMismatch { name, .. } if name == "blob" => "size",
+            ArchiveProblem::MissingFile { name, .. } if name == "zuck" => "missing",
+            ArchiveProblem::OrphanedFile { filename } if filename == "stray.png" => "orphaned",
+            problem => panic!("Unexpected problem: {:?}", problem),
+        })
+        .collect();
+    // The duplicate line of parrot is checked against the image too
+    assert_eq!(
+        kinds,
+        vec![
+            "malformed",
+            "duplicate",
+            "hash",
+            "size",
+            "missing",
+            "hash",
+            "orphaned"
+        ]
+    );
+    assert!(matches!(
+        report.into_result(),
+        Err(Error::ArchiveProblems { count: 7 })
+    ));
+}
+
+#[tokio::test]
+async fn test_upload_defers_aliases_and_skips_existing() {
+    let source = MockSlackServer::start().await;
+    // Listed before the emoji it aliases, so the upload must defer it
+    source.add_alias("parrot-alias", "parrot");
+    source.add_emoji("parrot", &png("parrot"));
+    source.add_emoji("already-there", &png("ours"));
+    source.add_emoji("seal", &png("seal"));
+    source.add_emoji("broken", &png("broken"));
+
+    let directory = source.download_archive().await;
+    let target_directory = directory.path().to_str().unwrap();
+
+    let destination = MockSlackServer::start().await;
+    destination.add_emoji("already-there", &png("theirs"));
+    destination.rate_limit_next(1, 0);
+    destination.fail_requests_for("emoji.add", "broken", "error_too_big");
+    let report = upload(
+        Arc::new(destination.client()),
+        target_directory,
+        &EmojiFilter::default(),
+        &UploadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(destination.get_image("parrot").unwrap(), png("parrot"));
+    assert_eq!(
+        destination.get_emoji("parrot-alias").unwrap().alias_for,
+        "parrot"
+    );
+    assert_eq!(
+        destination.get_image("already-there").unwrap(),
+        png("theirs")
+    );
+    // Conflicts with a standard Unicode emoji short code
+    assert!(destination.get_emoji("seal").is_none());
+    assert_eq!(destination.emojis().len(), 3);
+
+    let outcome_of = |name: &str| {
+        report
+            .emojis
+            .iter()
+            .find(|result| result.name == name)
+            .map(|result| result.outcome.clone())
+    };
+    assert_eq!(outcome_of("parrot-alias"), Some(EmojiOutcome::Uploaded));
+    assert_eq!(
+        outcome_of("already-there"),
+        Some(EmojiOutcome::SkippedExisting)
+    );
+    assert_eq!(
+        outcome_of("seal"),
+        Some(EmojiOutcome::SkippedStandardShortcode)
+    );
+    assert!(matches!(
+        outcome_of("broken"),
+        Some(EmojiOutcome::Failed(_))
+    ));
+    assert!(matches!(
+        report.into_result(),
+        Err(Error::Incomplete { failed: 1, .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_upload_resolves_alias_chains() {
+    let source = MockSlackServer::start().await;
+    source.add_alias("parrot-alias-alias", "parrot-alias");
+    source.add_alias("parrot-alias", "parrot");
+    source.add_emoji("parrot", &png("parrot"));
+    source.add_alias("thumbs", "+1");
+    source.add_alias("ghost", "missing");
+    source.add_alias("ping", "pong");
+    source.add_alias("pong", "ping");
+    source.add_emoji("broken", &png("broken"));
+    source.add_alias("broken-alias", "broken");
+
+    let directory = source.download_archive().await;
+    // Aliases whose image cannot be downloaded are archived all the same
+    let archived_names: Vec<String> = read_archive(&EmojiDirectory::new(directory.path()))
+        .await
+        .into_iter()
+        .map(|emoji_file| emoji_file.emoji.name)
+        .collect();
+    assert_eq!(archived_names.len(), 9);
+
+    let destination = MockSlackServer::start().await;
+    destination.fail_requests_for("emoji.add", "broken", "error_too_big");
+    let report = upload(
+        Arc::new(destination.client()),
+        directory.path(),
+        &EmojiFilter::default(),
+        &UploadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    // Aliases for aliases are added for the emoji at the end of the chain
+    assert_eq!(
+        destination.get_emoji("parrot-alias").unwrap().alias_for,
+        "parrot"
+    );
+    assert_eq!(
+        destination
+            .get_emoji("parrot-alias-alias")
+            .unwrap()
+            .alias_for,
+        "parrot"
+    );
+    assert_eq!(destination.get_emoji("thumbs").unwrap().alias_for, "+1");
+    // Dangling aliases, aliases in a cycle and aliases for images that failed are never sent
+    assert_eq!(destination.request_count("emoji.add"), 5);
+    let failures: HashSet<&str> = report.failures().map(|(name, _)| name).collect();
+    assert_eq!(
+        failures,
+        HashSet::from(["ghost", "ping", "pong", "broken", "broken-alias"])
+    );
+    assert!(report
+        .failures()
+        .any(|(name, reason)| name == "ping" && reason.contains("cycle")));
+}
+
+#[tokio::test]
+async fn test_upload_filters_emojis() {
+    let source = MockSlackServer::start().await;
+    source.add_emoji("parrot", &png("parrot"));
+    source.add_emoji("blob", &png("blob"));
+    source.add_emoji("blob-wave", &png("blob-wave"));
+    source.add_emoji("zuck", &png("zuck"));
+
+    let directory = source.download_archive().await;
+
+    let destination = MockSlackServer::start().await;
+    let filter = EmojiFilter {
+        names: Some(HashSet::from([
+            String::from("parrot"),
+            String::from("blob-wave"),
+            String::from("zuck"),
+        ])),
+        created_after: Some(
+            Utc.timestamp_opt(source.get_emoji("parrot").unwrap().created, 0)
+                .unwrap(),
+        ),
+        ..Default::default()
+    };
+    let report = upload(
+        Arc::new(destination.client()),
+        directory.path(),
+        &filter,
+        &UploadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(report.count(&EmojiOutcome::Uploaded), 2);
+    let mut uploaded_names: Vec<String> = destination
+        .emojis()
+        .into_iter()
+        .map(|emoji| emoji.name)
+        .collect();
+    uploaded_names.sort();
+    assert_eq!(uploaded_names, ["blob-wave", "zuck"]);
+}
+
+#[tokio::test]
+async fn test_upload_resolves_name_conflicts() {
+    let source = MockSlackServer::start().await;
+    source.add_emoji("parrot", &png("parrot"));
+    source.add_alias("parrot-alias", "parrot");
+    source.add_emoji("blob", &png("blob"));
+    source.add_emoji("seal", &png("seal"));
+
+    let directory = source.download_archive().await;
+
+    let start_destination = || async {
+        let destination = MockSlackServer::start().await;
+        destination.add_emoji("parrot", &png("theirs"));
+        destination.add_alias("their-parrot", "parrot");
+        destination.add_emoji("blob", &png("blob"));
+        destination
+    };
+    let outcome_of = |report: &RunReport, name: &str| {
+        report
+            .emojis
+            .iter()
+            .find(|result| result.name == name)
+            .map(|result| result.outcome.clone())
+    };
+
+    let destination = start_destination().await;
+    assert!(matches!(
+        upload(
+            Arc::new(destination.client()),
+            directory.path(),
+            &EmojiFilter::default(),
+            &UploadOptions {
+                on_conflict: ConflictStrategy::Rename {
+                    prefix: String::new(),
+                    suffix: String::new(),
+                },
+                ..Default::default()
+            },
+        )
+        .await,
+        Err(Error::InvalidInput(_))
+    ));
+
+    let report = upload(
+        Arc::new(destination.client()),
+        directory.path(),
+        &EmojiFilter::default(),
+        &UploadOptions {
+            on_conflict: ConflictStrategy::Rename {
+                prefix: String::from("acme-"),
+                suffix: String::new(),
+            },
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        outcome_of(&report, "parrot"),
+        Some(EmojiOutcome::Renamed(String::from("acme-parrot")))
+    );
+    assert_eq!(
+        outcome_of(&report, "seal"),
+        Some(EmojiOutcome::Renamed(String::from("acme-seal")))
+    );
+    assert_eq!(
+        outcome_of(&report, "parrot-alias"),
+        Some(EmojiOutcome::Uploaded)
+    );
+    assert_eq!(destination.get_image("parrot").unwrap(), png("theirs"));
+    assert_eq!(destination.get_image("acme-parrot").unwrap(), png("parrot"));
+    assert_eq!(destination.get_image("acme-blob").unwrap(), png("blob"));
+    // Aliases follow the emoji they alias to its new name
+    assert_eq!(
+        destination.get_emoji("parrot-alias").unwrap().alias_for,
+        "acme-parrot"
+    );
+
+    let destination = start_destination().await;
+    let report = upload(
+        Arc::new(destination.client()),
+        directory.path(),
+        &EmojiFilter::default(),
+        &UploadOptions {
+            on_conflict: ConflictStrategy::Overwrite,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        outcome_of(&report, "parrot"),
+        Some(EmojiOutcome::Overwritten)
+    );
+    assert_eq!(
+        outcome_of(&report, "blob"),
+        Some(EmojiOutcome::SkippedExisting)
+    );
+    assert_eq!(
+        outcome_of(&report, "seal"),
+        Some(EmojiOutcome::SkippedStandardShortcode)
+    );
+    assert_eq!(destination.get_image("parrot").unwrap(), png("parrot"));
+    // Aliases for the overwritten emoji are added back
+    assert_eq!(
+        destination.get_emoji("their-parrot").unwrap().alias_for,
+        "parrot"
+    );
+    assert_eq!(destination.request_count("emoji.remove"), 1);
+
+    // An emoji that cannot be replaced is put back along with the aliases for it
+    let destination = start_destination().await;
+    destination.fail_next_request_for("emoji.add", "parrot", "error_too_big");
+    let report = upload(
+        Arc::new(destination.client()),
+        directory.path(),
+        &EmojiFilter::default(),
+        &UploadOptions {
+            on_conflict: ConflictStrategy::Overwrite,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        outcome_of(&report, "parrot"),
+        Some(EmojiOutcome::Failed(_))
+    ));
+    assert_eq!(destination.get_image("parrot").unwrap(), png("theirs"));
+    assert_eq!(
+        destination.get_emoji("their-parrot").unwrap().alias_for,
+        "parrot"
+    );
+    assert!(matches!(
+        report.into_result(),
+        Err(Error::Incomplete { .. })
+    ));
+
+    // So is one whose aliases cannot be added back
+    let destination = start_destination().await;
+    destination.fail_next_request_for("emoji.add", "their-parrot", "error_too_big");
+    let report = upload(
+        Arc::new(destination.client()),
+        directory.path(),
+        &EmojiFilter::default(),
+        &UploadOptions {
+            on_conflict: ConflictStrategy::Overwrite,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        outcome_of(&report, "parrot"),
+        Some(EmojiOutcome::Failed(_))
+    ));
+    assert_eq!(destination.get_image("parrot").unwrap(), png("theirs"));
+    assert_eq!(
+        destination.get_emoji("their-parrot").unwrap().alias_for,
+        "parrot"
+    );
+}
+
+#[tokio::test]
+async fn test_upload_fails_with_typed_errors() {
+    let server = MockSlackServer::start().await;
+    let directory = tempdir().unwrap();
+
+    let missing_directory = directory.path().join("missing");
+    assert!(matches!(
+        upload(
+            Arc::new(server.client()),
+            &missing_directory,
+            &EmojiFilter::default(),
+            &UploadOptions::default()
+        )
+        .await,
+        Err(Error::NotADirectory(path)) if path == missing_directory
+    ));
+    // Rather than reporting every emoji in the workspace as missing from the archive
+    assert!(matches!(
+        diff(Arc::new(server.client()), &missing_directory, false).await,
+        Err(Error::NotADirectory(path)) if path == missing_directory
+    ));
+
+    let client = SlackClient::new_with_base_url("xoxc-wrong", "cookie", server.api_base_url());
+    assert!(matches!(
+        upload(Arc::new(client), directory.path(), &EmojiFilter::default(), &UploadOptions::default()).await,
+        Err(Error::Auth { code }) if code == "invalid_auth"
+    ));
+    assert_eq!(server.request_count("emoji.add"), 0);
+}
+
+#[tokio::test]
+async fn test_upload_resumes_from_journal() {
+    let source = MockSlackServer::start().await;
+    source.add_emoji("parrot", &png("parrot"));
+    source.add_emoji("blob", &png("blob"));
+    let directory = source.download_archive().await;
+
+    let destination = MockSlackServer::start().await;
+    destination.fail_requests_for("emoji.add", "blob", "error_bad_upload");
+    let report = upload(
+        Arc::new(destination.client()),
+        directory.path(),
+        &EmojiFilter::default(),
+        &UploadOptions::default(),
+    )
+    .await
+    .unwrap();
+    assert!(report.has_failures());
+
+    // Resuming against a workspace that got blob in the meantime only tries blob again, and without fetching the
+    // workspace's emojis first since the journal is recent
+    let destination = MockSlackServer::start().await;
+    destination.add_emoji("blob", &png("blob"));
+    let hour = Duration::from_secs(60 * 60);
+    let report = upload(
+        Arc::new(destination.client()),
+        directory.path(),
+        &EmojiFilter::default(),
+        &UploadOptions {
+            resume: true,
+            refetch_after: hour,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(destination.request_count("emoji.adminList"), 0);
+    assert_eq!(destination.request_count("emoji.add"), 1);
+    assert_eq!(report.emojis.len(), 1);
+    assert_eq!(report.emojis[0].name, "blob");
+    assert_eq!(report.emojis[0].outcome, EmojiOutcome::SkippedExisting);
+
+    // Everything is done now, so a further resume has nothing to upload
+    let report = upload(
+        Arc::new(destination.client()),
+        directory.path(),
+        &EmojiFilter::default(),
+        &UploadOptions {
+            resume: true,
+            refetch_after: Duration::ZERO,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(destination.request_count("emoji.adminList"), 1);
+    assert!(report.emojis.is_empty());
+}
+
+#[tokio::test]
+async fn test_dry_runs_change_nothing() {
+    let source = MockSlackServer::start().await;
+    source.add_emoji("parrot", &png("parrot"));
+    source.add_emoji("blob", &png("blob"));
+    source.add_alias("parrot-alias", "parrot");
+
+    let directory = tempdir().unwrap();
+    let archive_path = directory.path().join("archive");
+    let no_filter = EmojiFilter::default();
+    let download_to_archive = |dry_run| {
+        let (client, path, filter) = (Arc::new(source.client()), &archive_path, &no_filter);
+        async move {
+            download(
+                client,
+                path,
+                EmojiStreamParameters::default(),
+                filter,
+                &DownloadOptions {
+                    mark_deleted: true,
+                    dry_run,
+                    ..Default::default()
+                },
+            )
+            .await
+        }
+    };
+    let report = download_to_archive(true).await.unwrap();
+    assert!(report.dry_run);
+    assert_eq!(report.count(&EmojiOutcome::Downloaded), 3);
+    assert!(!archive_path.exists());
+
+    download_to_archive(false).await.unwrap();
+    source.replace_emoji("blob", &png("new blob"));
+    source.remove_emoji("parrot-alias");
+    let report = download_to_archive(true).await.unwrap();
+    assert_eq!(report.count(&EmojiOutcome::Updated), 1);
+    assert_eq!(report.count(&EmojiOutcome::MarkedDeleted), 1);
+    let emoji_files = read_archive(&EmojiDirectory::new(&archive_path)).await;
+    assert!(emoji_files
+        .iter()
+        .all(|emoji_file| emoji_file.revision == 1 && emoji_file.deleted_upstream_at.is_none()));
+
+    let destination = MockSlackServer::start().await;
+    destination.add_emoji("blob", &png("theirs"));
+    let report = upload(
+        Arc::new(destination.client()),
+        &archive_path,
+        &EmojiFilter::default(),
+        &UploadOptions {
+            dry_run: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(report.dry_run);
+    assert_eq!(report.count(&EmojiOutcome::Uploaded), 2);
+    assert_eq!(report.count(&EmojiOutcome::SkippedExisting), 1);
+    assert_eq!(destination.request_count("emoji.add"), 0);
+    assert!(!archive_path.join("upload-journal.ndjson").exists());
+}
+
+#[tokio::test]
+async fn test_delete_selected_emojis() {
+    let server = MockSlackServer::start().await;
+    server.add_emoji("party-parrot", &png("parrot"));
+    server.add_alias("party-parrot-alias", "party-parrot");
+    server.add_emoji("party-blob", &png("blob"));
+    server.add_alias("blob-alias", "party-blob");
+    server.add_emoji("keeper", &png("keeper"));
+    server.add_alias("keeper-alias", "keeper");
+
+    let filter = || EmojiFilter {
+        name_patterns: vec![NamePattern::Regex(Regex::new("^party-").unwrap())],
+        ..Default::default()
+    };
+    let report = delete(Arc::new(server.client()), filter(), false, true)
+        .await
+        .unwrap();
+    // Including the alias that goes along with party-blob
+    assert_eq!(report.count(&EmojiOutcome::Deleted), 4);
+    assert_eq!(server.request_count("emoji.remove"), 0);
+
+    server.fail_requests_for("emoji.remove", "party-blob", "internal_error");
+    let report = delete(Arc::new(server.client()), filter(), true, false)
+        .await
+        .unwrap();
+    assert_eq!(report.count(&EmojiOutcome::Deleted), 2);
+    // A partial delete fails the run, so that the CLI exits with a non-zero code
+    assert!(matches!(
+        report.into_result(),
+        Err(Error::Incomplete { failed: 1, .. })
+    ));
+
+    let mut remaining: Vec<String> = server.emojis().into_iter().map(|e| e.name).collect();
+    remaining.sort();
+    assert_eq!(
+        remaining,
+        vec!["blob-alias", "keeper", "keeper-alias", "party-blob"]
+    );
+
+    let report = delete(
+        Arc::new(server.client()),
+        EmojiFilter {
+            names: Some(HashSet::from([String::from("keeper")])),
+            ..Default::default()
+        },
+        true,
+        false,
+    )
+    .await
+    .unwrap();
+    let deleted_names: Vec<&str> = report
+        .emojis
+        .iter()
+        .map(|result| result.name.as_str())
+        .collect();
+    assert_eq!(deleted_names, ["keeper", "keeper-alias"]);
+    assert_eq!(report.count(&EmojiOutcome::Deleted), 2);
+    assert!(server.get_emoji("keeper-alias").is_none());
+
+    assert!(matches!(
+        delete(
+            Arc::new(server.client()),
+            EmojiFilter::default(),
+            true,
+            false
+        )
+        .await,
+        Err(Error::InvalidInput(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_rename_repoints_aliases() {
+    let server = MockSlackServer::start().await;
+    server.add_emoji("partyparot", &png("parrot"));
+    server.add_alias("parrot-alias", "partyparot");
+    server.add_alias("another-alias", "partyparot");
+
+    let directory = server.download_archive().await;
+
+    rename(
+        Arc::new(server.client()),
+        "partyparot",
+        "partyparrot",
+        Some(directory.path()),
+    )
+    .await
+    .unwrap();
+
+    assert!(server.get_emoji("partyparot").is_none());
+    assert_eq!(server.get_image("partyparrot").unwrap(), png("parrot"));
+    for alias in ["parrot-alias", "another-alias"] {
+        assert_eq!(server.get_emoji(alias).unwrap().alias_for, "partyparrot");
+    }
+
+    let mut emoji_names: Vec<(String, String)> =
+        read_archive(&EmojiDirectory::new(directory.path()))
+            .await
+            .into_iter()
+            .map(|emoji_file| (emoji_file.emoji.name, emoji_file.emoji.alias_for))
+            .collect();
+    emoji_names.sort();
+    assert_eq!(
+        emoji_names,
+        vec![
+            (String::from("another-alias"), String::from("partyparrot")),
+            (String::from("parrot-alias"), String::from("partyparrot")),
+            (String::from("partyparrot"), String::new()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_rename_rolls_back_on_failure() {
+    let server = MockSlackServer::start().await;
+    server.add_emoji("partyparot", &png("parrot"));
+    server.add_alias("parrot-alias", "partyparot");
+    server.fail_requests_for("emoji.remove", "partyparot", "not_allowed");
+
+    assert!(matches!(
+        rename(
+            Arc::new(server.client()),
+            "partyparot",
+            "partyparrot",
+            None::<&str>
+        )
+        .await,
+        Err(Error::SlackApi { code, .. }) if code == "not_allowed"
+    ));
+
+    assert!(server.get_emoji("partyparrot").is_none());
+    assert_eq!(server.get_image("partyparot").unwrap(), png("parrot"));
+    assert_eq!(
+        server.get_emoji("parrot-alias").unwrap().alias_for,
+        "partyparot"
+    );
+    assert_eq!(server.emojis().len(), 2);
+
+    // An archive that cannot be updated stops the rename before anything changes
+    let add_count = server.request_count("emoji.add");
+    let directory = tempdir().unwrap();
+    let missing_directory = directory.path().join("missing");
+    assert!(matches!(
+        rename(
+            Arc::new(server.client()),
+            "partyparot",
+            "partyparrot",
+            Some(&missing_directory)
+        )
+        .await,
+        Err(Error::NotADirectory(path)) if path == missing_directory
+    ));
+    assert!(matches!(
+        rename(
+            Arc::new(server.client()),
+            "partyparot",
+            "partyparrot",
+            Some(directory.path())
+        )
+        .await,
+        Err(Error::InvalidInput(_))
+    ));
+    assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+    assert!(server.get_emoji("partyparrot").is_none());
+    assert_eq!(server.request_count("emoji.add"), add_count);
+}
+
+#[tokio::test]
+async fn test_sync_uploads_only_missing_emojis() {
+    let source = MockSlackServer::start().await;
+    source.add_alias("parrot-alias", "parrot");
+    source.add_emoji("parrot", &png("parrot"));
+    source.add_emoji("already-there", &png("ours"));
+    source.add_emoji("seal", &png("seal"));
+
+    let destination = MockSlackServer::start().await;
+    destination.add_emoji("already-there", &png("theirs"));
+
+    let directory = tempdir().unwrap();
+    sync(
+        Arc::new(source.client()),
+        Arc::new(destination.client()),
+        directory.path(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(destination.get_image("parrot").unwrap(), png("parrot"));
+    assert_eq!(
+        destination.get_emoji("parrot-alias").unwrap().alias_for,
+        "parrot"
+    );
+    assert_eq!(
+        destination.get_image("already-there").unwrap(),
+        png("theirs")
+    );
+    assert_eq!(destination.emojis().len(), 3);
+
+    // Only emojis missing from the destination were downloaded
+    let emoji_directory = EmojiDirectory::new(directory.path());
+    let emoji_files = read_archive(&emoji_directory).await;
+    assert!(emoji_files
+        .iter()
+        .all(|emoji_file| emoji_file.emoji.name != "already-there"));
+
+    // An archive kept from an earlier sync is brought up to date with the source
+    source.replace_emoji("parrot", &png("parrot!"));
+    emoji_directory
+        .mark_deleted_upstream(&HashSet::from([String::from("seal")]), Utc::now())
+        .await
+        .unwrap();
+    let destination = MockSlackServer::start().await;
+    sync(
+        Arc::new(source.client()),
+        Arc::new(destination.client()),
+        directory.path(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(destination.get_image("parrot").unwrap(), png("parrot!"));
+    let emoji_files = read_archive(&emoji_directory).await;
+    let parrot = emoji_files
+        .iter()
+        .find(|emoji_file| emoji_file.emoji.name == "parrot")
+        .unwrap();
+    assert_eq!(parrot.revision, 2);
+    assert!(emoji_files
+        .iter()
+        .all(|emoji_file| emoji_file.deleted_upstream_at.is_none()));
+}
+
+#[tokio::test]
+async fn test_sync_reports_images_that_fail_to_download() {
+    let source = MockSlackServer::start().await;
+    source.add_emoji("aaa", &png("aaa"));
+    source.add_emoji("bad", b"not an image");
+    source.add_emoji("zzz", &png("zzz"));
+
+    let destination = MockSlackServer::start().await;
+    let directory = tempdir().unwrap();
+    let report = sync(
+        Arc::new(source.client()),
+        Arc::new(destination.client()),
+        directory.path(),
+    )
+    .await
+    .unwrap();
+
+    // The emojis after the one that failed are still synced
+    assert_eq!(destination.get_image("aaa").unwrap(), png("aaa"));
+    assert_eq!(destination.get_image("zzz").unwrap(), png("zzz"));
+    assert!(destination.get_emoji("bad").is_none());
+    assert_eq!(report.count(&EmojiOutcome::Uploaded), 2);
+    assert_eq!(
+        report.failures().map(|(name, _)| name).collect::<Vec<_>>(),
+        ["bad"]
+    );
+    assert!(report.errors.is_empty());
+    assert!(matches!(
+        report.into_result(),
+        Err(Error::Incomplete { failed: 1, .. })
+    ));
+}
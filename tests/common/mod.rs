@@ -0,0 +1,512 @@
+//! An in-process stand-in for the parts of Slack's API that this tool talks to, for use in tests. It implements
+//! auth.test, emoji.adminList (with paging), emoji.add (in both "data" and "alias" modes), emoji.remove, rate
+//! limiting via `retry-after`, and hosting of the uploaded emoji images.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::{Form, Multipart, Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router, Server};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use slack_emoji::filter::EmojiFilter;
+use slack_emoji::{download, DownloadOptions, EmojiStreamParameters, RetryPolicy, SlackClient};
+use tempfile::{tempdir, TempDir};
+
+pub const MOCK_TOKEN: &str = "xoxc-mock-token";
+pub const MOCK_SESSION_COOKIE: &str = "mock-session-cookie";
+const MOCK_TEAM_ID: &str = "T0MOCK";
+const MOCK_TEAM_NAME: &str = "Mock Team";
+const MOCK_USER_ID: &str = "U0MOCK";
+const MOCK_USER_NAME: &str = "mock.user";
+const MOCK_USER_DISPLAY_NAME: &str = "Mock User";
+/// Image URLs handed out by the mock point at the real Slack CDN host; clients reach the mock's copy of an image by
+/// way of `SlackClient::emoji_cdn_url`
+const MOCK_EMOJI_CDN_URL: &str = "https://emoji.slack-edge.com";
+const MOCK_CREATED_BASE_TS: i64 = 1_600_000_000;
+/// A few of the standard emojis that the mock accepts aliases for
+const MOCK_STANDARD_SHORTCODES: [&str; 3] = ["+1", "tada", "seal"];
+
+#[derive(Debug, Clone)]
+pub struct MockEmoji {
+    pub name: String,
+    pub alias_for: String,
+    pub url: String,
+    pub added_by: String,
+    pub created: i64,
+}
+
+#[derive(Debug, Default)]
+struct MockSlackState {
+    emojis: Vec<MockEmoji>,
+    /// Image bytes keyed by the path of the emoji's URL
+    images: HashMap<String, Vec<u8>>,
+    pending_rate_limits: u32,
+    retry_after_secs: u64,
+    request_counts: HashMap<String, u32>,
+    /// Slack error codes to respond with, keyed by endpoint and the name of the emoji in the request, and whether
+    /// to respond with them only once
+    failures: HashMap<(String, String), (String, bool)>,
+    /// Endpoints that the mock user is not allowed to use
+    denied_endpoints: HashSet<String>,
+}
+
+impl MockSlackState {
+    fn find(&self, name: &str) -> Option<&MockEmoji> {
+        self.emojis.iter().find(|emoji| emoji.name == name)
+    }
+
+    fn next_created_ts(&self) -> i64 {
+        MOCK_CREATED_BASE_TS + self.emojis.len() as i64
+    }
+
+    fn insert_image(&mut self, name: &str, filename: &str, image: Vec<u8>) {
+        let mut hasher = DefaultHasher::new();
+        image.hash(&mut hasher);
+        let extension = filename.rsplit_once('.').map_or("png", |(_, ext)| ext);
+        let path = format!(
+            "/{}/{}/{:016x}.{}",
+            MOCK_TEAM_ID,
+            name,
+            hasher.finish(),
+            extension
+        );
+
+        self.emojis.push(MockEmoji {
+            name: name.to_string(),
+            alias_for: String::new(),
+            url: format!("{}{}", MOCK_EMOJI_CDN_URL, path),
+            added_by: MOCK_USER_DISPLAY_NAME.to_string(),
+            created: self.next_created_ts(),
+        });
+        self.images.insert(path, image);
+    }
+
+    fn insert_alias(&mut self, name: &str, alias_for: &str) {
+        // Stands in until the aliased emoji is known, so that tests can seed aliases in any order; see `url_of`
+        self.emojis.push(MockEmoji {
+            name: name.to_string(),
+            alias_for: alias_for.to_string(),
+            url: format!(
+                "{}/{}/{}/missing.png",
+                MOCK_EMOJI_CDN_URL, MOCK_TEAM_ID, alias_for
+            ),
+            added_by: MOCK_USER_DISPLAY_NAME.to_string(),
+            created: self.next_created_ts(),
+        });
+    }
+
+    /// Slack reports the image URL of the aliased emoji for an alias, which 404s if there is no such emoji. Aliases
+    /// for aliases, which Slack does not allow but tests seed, are followed to the end of the chain.
+    fn url_of<'a>(&'a self, mut emoji: &'a MockEmoji) -> &'a str {
+        for _ in 0..self.emojis.len() {
+            match self.find(&emoji.alias_for) {
+                Some(alias_for) => emoji = alias_for,
+                None => break,
+            }
+        }
+        &emoji.url
+    }
+
+    fn permission_denied(&self, endpoint: &str) -> Option<Response> {
+        self.denied_endpoints
+            .contains(endpoint)
+            .then(|| slack_error("not_allowed_token_type"))
+    }
+
+    fn injected_failure(&mut self, endpoint: &str, name: &str) -> Option<Response> {
+        let key = (endpoint.to_string(), name.to_string());
+        let (error, once) = self.failures.get(&key)?.clone();
+        if once {
+            self.failures.remove(&key);
+        }
+        Some(slack_error(&error))
+    }
+
+    /// Counts the request and, if a rate limit is pending, returns the response to send instead of handling it
+    fn intercept(&mut self, endpoint: &str) -> Option<Response> {
+        *self.request_counts.entry(endpoint.to_string()).or_default() += 1;
+        if self.pending_rate_limits == 0 {
+            return None;
+        }
+        self.pending_rate_limits -= 1;
+        Some(
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, self.retry_after_secs.to_string())],
+                Json(json!({"ok": false, "error": "ratelimited"})),
+            )
+                .into_response(),
+        )
+    }
+}
+
+type SharedState = Arc<Mutex<MockSlackState>>;
+
+pub struct MockSlackServer {
+    address: SocketAddr,
+    state: SharedState,
+}
+
+impl MockSlackServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let state = SharedState::default();
+
+        let app = Router::new()
+            .route("/api/auth.test", post(auth_test))
+            .route("/api/emoji.adminList", post(admin_list))
+            .route("/api/emoji.add", post(add))
+            .route("/api/emoji.remove", post(remove))
+            .route("/cdn/*path", get(image))
+            .with_state(state.clone());
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        Self { address, state }
+    }
+
+    pub fn api_base_url(&self) -> String {
+        format!("http://{}/api", self.address)
+    }
+
+    pub fn emoji_cdn_url(&self) -> Url {
+        Url::parse(&format!("http://{}/cdn", self.address)).unwrap()
+    }
+
+    /// A client pointed at this server that neither waits between writes nor backs off for long when retrying
+    pub fn client(&self) -> SlackClient {
+        SlackClient::new_with_base_url(MOCK_TOKEN, MOCK_SESSION_COOKIE, self.api_base_url())
+            .with_emoji_cdn_url(Some(self.emoji_cdn_url()))
+            .with_write_delay(Duration::ZERO)
+            .with_retry_policy(RetryPolicy {
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(50),
+                ..Default::default()
+            })
+    }
+
+    /// Downloads every emoji in the workspace into a new archive, for tests that start from one
+    pub async fn download_archive(&self) -> TempDir {
+        let directory = tempdir().unwrap();
+        download(
+            Arc::new(self.client()),
+            directory.path(),
+            EmojiStreamParameters::default(),
+            &EmojiFilter::default(),
+            &DownloadOptions::default(),
+        )
+        .await
+        .unwrap();
+        directory
+    }
+
+    pub fn add_emoji(&self, name: &str, image: &[u8]) {
+        self.state
+            .lock()
+            .unwrap()
+            .insert_image(name, &format!("{}.png", name), image.to_vec());
+    }
+
+    /// Deletes the emoji called `name` and adds a new one by the same name with `image`, which is given a new URL
+    pub fn replace_emoji(&self, name: &str, image: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.emojis.retain(|emoji| emoji.name != name);
+        state.insert_image(name, &format!("{}.png", name), image.to_vec());
+    }
+
+    pub fn remove_emoji(&self, name: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .emojis
+            .retain(|emoji| emoji.name != name);
+    }
+
+    pub fn add_alias(&self, name: &str, alias_for: &str) {
+        self.state.lock().unwrap().insert_alias(name, alias_for);
+    }
+
+    /// Responds to the next `count` API requests with HTTP 429 and a `retry-after` of `retry_after_secs`
+    pub fn rate_limit_next(&self, count: u32, retry_after_secs: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.pending_rate_limits = count;
+        state.retry_after_secs = retry_after_secs;
+    }
+
+    /// Responds to every request to `endpoint` for the emoji named `name` with the Slack error code `error`
+    pub fn fail_requests_for(&self, endpoint: &str, name: &str, error: &str) {
+        self.state.lock().unwrap().failures.insert(
+            (endpoint.to_string(), name.to_string()),
+            (error.to_string(), false),
+        );
+    }
+
+    /// Like `fail_requests_for`, but only the next such request fails
+    pub fn fail_next_request_for(&self, endpoint: &str, name: &str, error: &str) {
+        self.state.lock().unwrap().failures.insert(
+            (endpoint.to_string(), name.to_string()),
+            (error.to_string(), true),
+        );
+    }
+
+    /// Responds to every authenticated request to `endpoint` as if the mock user were not allowed to use it
+    pub fn deny_permission(&self, endpoint: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .denied_endpoints
+            .insert(endpoint.to_string());
+    }
+
+    pub fn emojis(&self) -> Vec<MockEmoji> {
+        self.state.lock().unwrap().emojis.clone()
+    }
+
+    pub fn get_emoji(&self, name: &str) -> Option<MockEmoji> {
+        self.state.lock().unwrap().find(name).cloned()
+    }
+
+    pub fn get_image(&self, name: &str) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        let url = Url::parse(state.url_of(state.find(name)?)).unwrap();
+        state.images.get(url.path()).cloned()
+    }
+
+    pub fn request_count(&self, endpoint: &str) -> u32 {
+        let state = self.state.lock().unwrap();
+        state.request_counts.get(endpoint).copied().unwrap_or(0)
+    }
+}
+
+fn slack_error(error: &str) -> Response {
+    Json(json!({"ok": false, "error": error})).into_response()
+}
+
+/// Returns the Slack error code to respond with if the request is not authenticated
+fn check_auth(token: Option<&str>, headers: &HeaderMap) -> Option<&'static str> {
+    let expected_cookie = format!("d={}", MOCK_SESSION_COOKIE);
+    let has_cookie = headers
+        .get(header::COOKIE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split("; ").any(|c| c == expected_cookie));
+    match token {
+        None => Some("not_authed"),
+        Some(MOCK_TOKEN) if has_cookie => None,
+        Some(_) => Some("invalid_auth"),
+    }
+}
+
+#[derive(Deserialize)]
+struct AuthTestParams {
+    token: Option<String>,
+}
+
+async fn auth_test(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Form(params): Form<AuthTestParams>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    if let Some(response) = state.intercept("auth.test") {
+        return response;
+    }
+    if let Some(error) = check_auth(params.token.as_deref(), &headers) {
+        return slack_error(error);
+    }
+
+    Json(json!({
+        "ok": true,
+        "url": "https://mock.slack.com/",
+        "team": MOCK_TEAM_NAME,
+        "user": MOCK_USER_NAME,
+        "team_id": MOCK_TEAM_ID,
+        "user_id": MOCK_USER_ID,
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct AdminListParams {
+    token: Option<String>,
+    count: Option<usize>,
+    page: Option<usize>,
+}
+
+async fn admin_list(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Form(params): Form<AdminListParams>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    if let Some(response) = state.intercept("emoji.adminList") {
+        return response;
+    }
+    if let Some(error) = check_auth(params.token.as_deref(), &headers) {
+        return slack_error(error);
+    }
+    if let Some(response) = state.permission_denied("emoji.adminList") {
+        return response;
+    }
+
+    let count = params.count.unwrap_or(100).max(1);
+    let page = params.page.unwrap_or(1).max(1);
+    let total = state.emojis.len();
+    let emojis: Vec<Value> = state
+        .emojis
+        .iter()
+        .skip((page - 1) * count)
+        .take(count)
+        .map(|emoji| {
+            json!({
+                "name": emoji.name,
+                "is_alias": i32::from(!emoji.alias_for.is_empty()),
+                "alias_for": emoji.alias_for,
+                "url": state.url_of(emoji),
+                "created": emoji.created,
+                "team_id": MOCK_TEAM_ID,
+                "user_id": MOCK_USER_ID,
+                "user_display_name": emoji.added_by,
+                "can_delete": true,
+                "is_bad": false,
+                "synonyms": [],
+            })
+        })
+        .collect();
+
+    Json(json!({
+        "ok": true,
+        "emoji": emojis,
+        "disabled_emoji": [],
+        "custom_emoji_total_count": total,
+        "paging": {
+            "count": count,
+            "total": total,
+            "page": page,
+            "pages": total.div_ceil(count),
+        },
+    }))
+    .into_response()
+}
+
+async fn add(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut image: Option<(String, Vec<u8>)> = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        let field_name = field.name().unwrap_or_default().to_string();
+        if field_name == "image" {
+            let filename = field.file_name().unwrap_or_default().to_string();
+            image = Some((filename, field.bytes().await.unwrap().to_vec()));
+        } else {
+            fields.insert(field_name, field.text().await.unwrap());
+        }
+    }
+
+    let mut state = state.lock().unwrap();
+    if let Some(response) = state.intercept("emoji.add") {
+        return response;
+    }
+    if let Some(error) = check_auth(fields.get("token").map(String::as_str), &headers) {
+        return slack_error(error);
+    }
+    if let Some(response) = state.permission_denied("emoji.add") {
+        return response;
+    }
+
+    let name = match fields.get("name") {
+        Some(name) if !name.is_empty() => name,
+        _ => return slack_error("invalid_name_required"),
+    };
+    if let Some(response) = state.injected_failure("emoji.add", name) {
+        return response;
+    }
+    if state.find(name).is_some() {
+        return slack_error("error_name_taken");
+    }
+
+    match fields.get("mode").map(String::as_str) {
+        Some("data") => match image {
+            Some((filename, bytes)) if !bytes.is_empty() => {
+                state.insert_image(name, &filename, bytes);
+            }
+            _ => return slack_error("no_image_uploaded"),
+        },
+        // Like Slack, aliases can be for standard emojis but not for other aliases
+        Some("alias") => match fields.get("alias_for") {
+            Some(alias_for)
+                if MOCK_STANDARD_SHORTCODES.contains(&alias_for.as_str())
+                    || state
+                        .find(alias_for)
+                        .is_some_and(|emoji| emoji.alias_for.is_empty()) =>
+            {
+                state.insert_alias(name, alias_for);
+            }
+            _ => return slack_error("error_invalid_alias"),
+        },
+        _ => return slack_error("invalid_mode"),
+    }
+
+    Json(json!({"ok": true})).into_response()
+}
+
+#[derive(Deserialize)]
+struct RemoveParams {
+    token: Option<String>,
+    name: Option<String>,
+}
+
+async fn remove(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Form(params): Form<RemoveParams>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    if let Some(response) = state.intercept("emoji.remove") {
+        return response;
+    }
+    if let Some(error) = check_auth(params.token.as_deref(), &headers) {
+        return slack_error(error);
+    }
+    if let Some(response) = state.permission_denied("emoji.remove") {
+        return response;
+    }
+
+    let name = params.name.unwrap_or_default();
+    if let Some(response) = state.injected_failure("emoji.remove", &name) {
+        return response;
+    }
+    if state.find(&name).is_none() {
+        return slack_error("emoji_not_found");
+    }
+    // Removing an emoji also removes any aliases for it
+    state
+        .emojis
+        .retain(|emoji| emoji.name != name && emoji.alias_for != name);
+
+    Json(json!({"ok": true})).into_response()
+}
+
+async fn image(State(state): State<SharedState>, Path(path): Path<String>) -> Response {
+    let state = state.lock().unwrap();
+    match state.images.get(&format!("/{}", path)) {
+        Some(bytes) => bytes.clone().into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}